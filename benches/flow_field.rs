@@ -0,0 +1,52 @@
+use std::time::Instant;
+use bevy::prelude::*;
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use amplitude::arena::ArenaBounds;
+use amplitude::flowfield::{FLOW_CELL_SIZE, FlowField};
+use amplitude::obstacle::{Obstacle, ObstacleLayout};
+use amplitude::terrain::Terrain;
+
+/// Mob counts to time, roughly matching waves 10 through 70 of the default wave table.
+const MOB_COUNTS: [usize; 5] = [100, 500, 1000, 2500, 5000];
+const FRAMES: u32 = 10;
+
+fn main() {
+    let mut prng = StdRng::seed_from_u64(0);
+    let bounds = ArenaBounds::default();
+    let layout = ObstacleLayout::generate(0, &Terrain::new(0));
+    let obstacles: Vec<&Obstacle> = layout.obstacles.iter().collect();
+
+    let mut field = FlowField::new(bounds.min, bounds.max, FLOW_CELL_SIZE);
+    field.set_obstacles(&obstacles);
+
+    // The field is only rebuilt when the player changes cell, at most once a tick.
+    let start = Instant::now();
+    for frame in 0..FRAMES {
+        field.compute((frame as i32, 0));
+    }
+    let rebuild = start.elapsed().as_secs_f64() * 1000.0 / FRAMES as f64;
+    println!("rebuild: {:.3} ms", rebuild);
+
+    println!("{:>6} {:>14}", "mobs", "sample (ms)");
+
+    for count in MOB_COUNTS {
+        let positions: Vec<Vec3> = (0..count)
+            .map(|_| Vec3::new(
+                bounds.min.x + prng.gen::<f32>() * (bounds.max.x - bounds.min.x),
+                0.5,
+                bounds.min.y + prng.gen::<f32>() * (bounds.max.y - bounds.min.y)
+            ))
+            .collect();
+
+        let start = Instant::now();
+        for _ in 0..FRAMES {
+            for position in positions.iter() {
+                std::hint::black_box(field.direction(*position));
+            }
+        }
+        let sample = start.elapsed().as_secs_f64() * 1000.0 / FRAMES as f64;
+
+        println!("{:>6} {:>14.3}", count, sample);
+    }
+}
@@ -0,0 +1,77 @@
+use std::time::Instant;
+use bevy::prelude::*;
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use amplitude::spatial::{inter_mob_force, pair_force, SpatialGrid};
+
+/// Mob counts to time, roughly matching waves 10 through 70 of the default wave table.
+const MOB_COUNTS: [usize; 5] = [100, 500, 1000, 2500, 5000];
+const FRAMES: u32 = 10;
+
+/// Mobs are spread across the playable area so the density stays comparable to a real wave.
+const ARENA_SIZE: f32 = 80.0;
+
+fn brute_force(
+    positions: &[(Entity, Vec3)]
+) -> Vec<Vec3> {
+    let mut forces = Vec::with_capacity(positions.len());
+
+    for (entity1, position1) in positions {
+        let mut force = Vec3::default();
+
+        for (entity2, position2) in positions {
+            if entity2 != entity1 {
+                force += pair_force(*position2 - *position1);
+            }
+        }
+
+        forces.push(force);
+    }
+
+    forces
+}
+
+fn grid(
+    grid: &mut SpatialGrid,
+    positions: &[(Entity, Vec3)]
+) -> Vec<Vec3> {
+    grid.clear();
+    for (entity, position) in positions {
+        grid.insert(*entity, *position);
+    }
+
+    positions
+        .iter()
+        .map(|(entity, position)| inter_mob_force(grid, *entity, *position))
+        .collect()
+}
+
+fn main() {
+    let mut prng = StdRng::seed_from_u64(0);
+    let mut spatial_grid = SpatialGrid::default();
+
+    println!("{:>6} {:>14} {:>14}", "mobs", "brute (ms)", "grid (ms)");
+
+    for count in MOB_COUNTS {
+        let positions: Vec<(Entity, Vec3)> = (0..count)
+            .map(|i| (
+                Entity::from_raw(i as u32),
+                Vec3::new(prng.gen::<f32>() * ARENA_SIZE, 0.5, prng.gen::<f32>() * ARENA_SIZE)
+            ))
+            .collect();
+
+        let start = Instant::now();
+        for _ in 0..FRAMES {
+            std::hint::black_box(brute_force(&positions));
+        }
+        let brute = start.elapsed().as_secs_f64() * 1000.0 / FRAMES as f64;
+
+        let start = Instant::now();
+        for _ in 0..FRAMES {
+            std::hint::black_box(grid(&mut spatial_grid, &positions));
+        }
+        let gridded = start.elapsed().as_secs_f64() * 1000.0 / FRAMES as f64;
+
+        println!("{:>6} {:>14.3} {:>14.3}", count, brute, gridded);
+    }
+}
@@ -0,0 +1,263 @@
+use bevy::{
+    prelude::*,
+};
+use iyes_loopless::prelude::*;
+use crate::health::{DamageEvent, DamageKind};
+use crate::mob::Mob;
+use crate::player::{Player, Projectile};
+use crate::simulation::PLAYER_SIZE;
+use crate::terrain::{GROUND_HALF_EXTENT, Terrain};
+
+/// What happens to something that moves past the edge of the arena.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BoundsBehaviour {
+    /// Stopped at the edge.
+    Clamp,
+    /// Reflected back in off the edge.
+    Bounce,
+    /// Comes back in from the opposite edge.
+    Wrap,
+    /// Free to leave, but takes damage every second it spends outside. Projectiles have no
+    /// health, so they are removed instead.
+    Damage { per_second: f32 },
+}
+
+/// Playable area on the ground plane, matching the extent of the generated surface.
+#[derive(Resource, Clone, Copy)]
+pub struct ArenaBounds {
+    pub min: Vec2,
+    pub max: Vec2,
+    pub player: BoundsBehaviour,
+    pub mobs: BoundsBehaviour,
+    pub projectiles: BoundsBehaviour
+}
+
+impl Default for ArenaBounds {
+    fn default() -> Self {
+        ArenaBounds {
+            min: Vec2::splat(-GROUND_HALF_EXTENT),
+            max: Vec2::splat(GROUND_HALF_EXTENT),
+            player: BoundsBehaviour::Clamp,
+            mobs: BoundsBehaviour::Clamp,
+            projectiles: BoundsBehaviour::Bounce
+        }
+    }
+}
+
+impl ArenaBounds {
+    pub fn contains(
+        &self,
+        pos: Vec3
+    ) -> bool {
+        pos.x >= self.min.x && pos.x <= self.max.x && pos.z >= self.min.y && pos.z <= self.max.y
+    }
+
+    /// Bring a body back inside the arena following `behaviour`. Returns whether it is still
+    /// outside afterwards, which only happens with `BoundsBehaviour::Damage`.
+    pub fn confine(
+        &self,
+        behaviour: BoundsBehaviour,
+        pos: &mut Vec3,
+        vel: &mut Vec3
+    ) -> bool {
+        match behaviour {
+            BoundsBehaviour::Clamp => {
+                confine_axis_clamp(&mut pos.x, &mut vel.x, self.min.x, self.max.x);
+                confine_axis_clamp(&mut pos.z, &mut vel.z, self.min.y, self.max.y);
+                false
+            }
+            BoundsBehaviour::Bounce => {
+                confine_axis_bounce(&mut pos.x, &mut vel.x, self.min.x, self.max.x);
+                confine_axis_bounce(&mut pos.z, &mut vel.z, self.min.y, self.max.y);
+                false
+            }
+            BoundsBehaviour::Wrap => {
+                pos.x = self.min.x + (pos.x - self.min.x).rem_euclid(self.max.x - self.min.x);
+                pos.z = self.min.y + (pos.z - self.min.y).rem_euclid(self.max.y - self.min.y);
+                false
+            }
+            BoundsBehaviour::Damage { .. } => !self.contains(*pos)
+        }
+    }
+}
+
+fn confine_axis_clamp(
+    pos: &mut f32,
+    vel: &mut f32,
+    min: f32,
+    max: f32
+) {
+    if *pos < min {
+        *pos = min;
+        *vel = vel.max(0.);
+    } else if *pos > max {
+        *pos = max;
+        *vel = vel.min(0.);
+    }
+}
+
+fn confine_axis_bounce(
+    pos: &mut f32,
+    vel: &mut f32,
+    min: f32,
+    max: f32
+) {
+    if *pos < min {
+        *pos = (2.0 * min - *pos).min(max);
+        *vel = vel.abs();
+    } else if *pos > max {
+        *pos = (2.0 * max - *pos).max(min);
+        *vel = -vel.abs();
+    }
+}
+
+/// Keep the player, mobs and projectiles inside the arena, after collisions have moved them.
+pub fn confine_to_arena(
+    mut commands: Commands,
+    bounds: Res<ArenaBounds>,
+    terrain: Res<Terrain>,
+    step: Res<FixedTimestepInfo>,
+    mut damage: EventWriter<DamageEvent>,
+    mut players: Query<(Entity, &mut Transform, &mut Player), (Without<Mob>, Without<Projectile>)>,
+    mut mobs: Query<(Entity, &mut Transform, &mut Mob), (Without<Player>, Without<Projectile>)>,
+    mut projectiles: Query<(Entity, &mut Transform, &mut Projectile), (Without<Player>, Without<Mob>)>
+) {
+    let dt = step.timestep().as_secs_f32();
+
+    for (entity, mut transform, mut player) in players.iter_mut() {
+        let player = &mut *player;
+
+        if bounds.confine(bounds.player, &mut player.pos, &mut player.vel) {
+            if let BoundsBehaviour::Damage { per_second } = bounds.player {
+                damage.send(DamageEvent {
+                    source: None,
+                    target: entity,
+                    amount: per_second * dt,
+                    kind: DamageKind::Arena
+                });
+            }
+        }
+
+        // Jumps are left alone, the player is only kept from sinking into the ground.
+        player.pos.y = player.pos.y.max(terrain.height(player.pos.x, player.pos.z) + PLAYER_SIZE * 0.5);
+        transform.translation = player.pos;
+    }
+
+    for (entity, mut transform, mut mob) in mobs.iter_mut() {
+        let mob = &mut *mob;
+
+        if bounds.confine(bounds.mobs, &mut mob.pos, &mut mob.vel) {
+            if let BoundsBehaviour::Damage { per_second } = bounds.mobs {
+                damage.send(DamageEvent {
+                    source: None,
+                    target: entity,
+                    amount: per_second * dt,
+                    kind: DamageKind::Arena
+                });
+            }
+        }
+
+        mob.pos.y = terrain.height(mob.pos.x, mob.pos.z) + mob.size * 0.5;
+        transform.translation = mob.pos;
+    }
+
+    for (entity, mut transform, mut projectile) in projectiles.iter_mut() {
+        let projectile = &mut *projectile;
+
+        if bounds.confine(bounds.projectiles, &mut projectile.pos, &mut projectile.vel) {
+            commands.entity(entity).despawn();
+            continue;
+        }
+
+        projectile.pos.y = terrain.height(projectile.pos.x, projectile.pos.z) + 0.5;
+        transform.translation = projectile.pos;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bounds() -> ArenaBounds {
+        ArenaBounds {
+            min: Vec2::splat(-10.0),
+            max: Vec2::splat(10.0),
+            ..default()
+        }
+    }
+
+    #[test]
+    fn clamp_stops_at_the_edge() {
+        let (mut pos, mut vel) = (Vec3::new(12.0, 1.0, -15.0), Vec3::new(3.0, 0., -1.0));
+
+        assert!(!bounds().confine(BoundsBehaviour::Clamp, &mut pos, &mut vel));
+        assert_eq!(pos, Vec3::new(10.0, 1.0, -10.0));
+        assert_eq!(vel, Vec3::ZERO);
+    }
+
+    #[test]
+    fn bounce_reflects_back_in() {
+        let (mut pos, mut vel) = (Vec3::new(12.0, 0., 0.), Vec3::new(3.0, 0., 0.));
+
+        bounds().confine(BoundsBehaviour::Bounce, &mut pos, &mut vel);
+        assert_eq!(pos.x, 8.0);
+        assert_eq!(vel.x, -3.0);
+    }
+
+    #[test]
+    fn wrap_comes_back_from_the_opposite_edge() {
+        let (mut pos, mut vel) = (Vec3::new(12.0, 0., -11.0), Vec3::X);
+
+        bounds().confine(BoundsBehaviour::Wrap, &mut pos, &mut vel);
+        assert_eq!(pos, Vec3::new(-8.0, 0., 9.0));
+        assert_eq!(vel, Vec3::X);
+    }
+
+    #[test]
+    fn damage_leaves_bodies_outside_and_reports_it() {
+        let behaviour = BoundsBehaviour::Damage { per_second: 1.0 };
+        let (mut pos, mut vel) = (Vec3::new(12.0, 0., 0.), Vec3::X);
+
+        assert!(bounds().confine(behaviour, &mut pos, &mut vel));
+        assert_eq!(pos.x, 12.0);
+
+        let mut inside = Vec3::new(5.0, 0., 5.0);
+        assert!(!bounds().confine(behaviour, &mut inside, &mut vel));
+    }
+    #[test]
+    fn bodies_inside_are_left_alone() {
+        let behaviours = [
+            BoundsBehaviour::Clamp,
+            BoundsBehaviour::Bounce,
+            BoundsBehaviour::Wrap,
+            BoundsBehaviour::Damage { per_second: 1.0 },
+        ];
+
+        for behaviour in behaviours {
+            let (mut pos, mut vel) = (Vec3::new(-9.5, 2.0, 3.0), Vec3::new(-1.0, 0., 1.0));
+
+            assert!(!bounds().confine(behaviour, &mut pos, &mut vel));
+            assert_eq!(pos, Vec3::new(-9.5, 2.0, 3.0));
+            assert_eq!(vel, Vec3::new(-1.0, 0., 1.0));
+        }
+    }
+
+    #[test]
+    fn bounce_never_overshoots_the_far_edge() {
+        let (mut pos, mut vel) = (Vec3::new(35.0, 0., 0.), Vec3::new(50.0, 0., 0.));
+
+        bounds().confine(BoundsBehaviour::Bounce, &mut pos, &mut vel);
+        assert_eq!(pos.x, -10.0);
+        assert_eq!(vel.x, -50.0);
+    }
+
+    #[test]
+    fn the_default_arena_covers_the_ground() {
+        let bounds = ArenaBounds::default();
+
+        assert!(bounds.contains(Vec3::new(GROUND_HALF_EXTENT, 0., -GROUND_HALF_EXTENT)));
+        assert!(!bounds.contains(Vec3::new(GROUND_HALF_EXTENT + 0.1, 0., 0.)));
+        assert_eq!(bounds.player, BoundsBehaviour::Clamp);
+        assert_eq!(bounds.projectiles, BoundsBehaviour::Bounce);
+    }
+}
@@ -0,0 +1,224 @@
+use std::env;
+use std::f32::consts::TAU;
+use std::time::{Duration, Instant};
+use bevy::{
+    prelude::*,
+};
+use bevy::asset::LoadState;
+use bevy::time::TimeUpdateStrategy;
+use iyes_loopless::prelude::*;
+use serde::Serialize;
+
+use amplitude::{GameState, GameStatePlugin};
+use amplitude::combat_log::CombatLog;
+use amplitude::obstacle::{ObstacleLayout, spawn_obstacles};
+use amplitude::player::{Player, PlayerPlugin};
+use amplitude::progression::{choose_upgrade, Experience, PendingUpgrades, ProgressionPlugin};
+use amplitude::replay::{Replay, replay_from_args, ReplayPlayback, ReplayPlugin, ReplayResult};
+use amplitude::rng::{seed_from_args, SimulationRng};
+use amplitude::simulation::{RunStats, SimulationPlugin, TickRate, Wave};
+use amplitude::terrain::Terrain;
+use amplitude::waves::{ActiveWaveTable, DEFAULT_WAVE_TABLE, WaveTable};
+
+/// Simulated seconds after which a game is called off, for players that can't die.
+pub const MAX_GAME_SECONDS: f32 = 1800.0;
+
+/// Number of games to run, e.g. `--games 20`. Game `i` uses seed `seed + i`.
+pub const GAMES_ARG: &str = "--games";
+/// Scripted player to use, `idle` or `circle`.
+pub const PLAYER_ARG: &str = "--player";
+
+/// Stand-in for the mouse in balancing runs.
+#[derive(Resource, Clone, Copy, Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum PlayerScript {
+    /// Never moves from the spawn point.
+    Idle,
+    /// Walks a circle around the spawn point.
+    Circle { radius: f32, period: f32 },
+}
+
+#[derive(Serialize)]
+struct GameReport {
+    seed: u64,
+    player: PlayerScript,
+    waves_survived: usize,
+    kills: usize,
+    damage_dealt: f32,
+    time_to_death: Option<f32>,
+    /// Whether a played back replay ended exactly like the recorded run.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    matches_replay: Option<bool>
+}
+
+fn main() {
+    // A replay is played back once and checked against how the recorded run ended.
+    if let Some(replay) = replay_from_args() {
+        let report = run_game(replay.seed, PlayerScript::Idle, Some(replay));
+        println!("{}", serde_json::to_string_pretty(&report).unwrap());
+
+        if report.matches_replay == Some(false) {
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let games: u64 = arg(GAMES_ARG).and_then(|games| games.parse().ok()).unwrap_or(1);
+    let player = match arg(PLAYER_ARG).as_deref() {
+        Some("circle") => PlayerScript::Circle { radius: 8.0, period: 10.0 },
+        Some("idle") | None => PlayerScript::Idle,
+        Some(other) => {
+            eprintln!("unknown player script {}, expected idle or circle", other);
+            std::process::exit(2);
+        }
+    };
+    let seed = seed_from_args();
+
+    let reports: Vec<GameReport> = (0..games)
+        .map(|game| run_game(seed.wrapping_add(game), player, None))
+        .collect();
+
+    println!("{}", serde_json::to_string_pretty(&reports).unwrap());
+}
+
+fn arg(
+    name: &str
+) -> Option<String> {
+    let args: Vec<String> = env::args().collect();
+    args.iter().position(|arg| arg == name).and_then(|i| args.get(i + 1).cloned())
+}
+
+fn run_game(
+    seed: u64,
+    player: PlayerScript,
+    replay: Option<Replay>
+) -> GameReport {
+    let mut app = App::new();
+
+    let expected = replay.as_ref().and_then(|replay| replay.result);
+    if let Some(replay) = replay {
+        app.insert_resource(TickRate(replay.tick_rate))
+            .insert_resource(ReplayPlayback::new(replay));
+    }
+    // Every game is appended to the same log, told apart by its seed.
+    if let Some(log) = CombatLog::from_args() {
+        app.insert_resource(log);
+    }
+
+    // Rendering assets are still created by the spawners, so their storages are registered
+    // without any of the render plugins.
+    app.add_plugins(MinimalPlugins)
+        .add_plugin(AssetPlugin::default())
+        .add_asset::<Mesh>()
+        .add_asset::<StandardMaterial>()
+        .insert_resource(SimulationRng::new(seed))
+        .insert_resource(player)
+        .add_plugin(GameStatePlugin)
+        .add_plugin(SimulationPlugin)
+        .add_plugin(PlayerPlugin)
+        .add_plugin(ProgressionPlugin)
+        // The surface isn't added, so obstacles are spawned here instead of with the world.
+        .add_startup_system(generate_obstacles)
+        // Takes the place of the mouse, so it runs once per frame like the click handler does.
+        .add_system(scripted_player.run_in_state(GameState::InGame))
+        // Picked once on entering the level up, so one level never applies two upgrades.
+        .add_enter_system(GameState::LevelUp, scripted_upgrade);
+
+    // Only played back here, balancing runs aren't worth recording.
+    if app.world.contains_resource::<ReplayPlayback>() {
+        app.add_plugin(ReplayPlugin);
+    }
+
+    // Step time by exactly one tick per update, so the run goes as fast as the CPU allows.
+    let tick = Duration::from_secs_f64(1.0 / app.world.resource::<TickRate>().0);
+    let mut now = Instant::now();
+    let mut step = |app: &mut App| {
+        now += tick;
+        app.insert_resource(TimeUpdateStrategy::ManualInstant(now));
+        app.update();
+    };
+
+    // Wait on the wave table before the game starts, so loading doesn't count as play time.
+    loop {
+        step(&mut app);
+
+        let handle = &app.world.resource::<ActiveWaveTable>().0;
+        if app.world.resource::<Assets<WaveTable>>().get(handle).is_some() {
+            break;
+        }
+
+        // A missing or broken table would otherwise be waited on forever.
+        if app.world.resource::<AssetServer>().get_load_state(handle) == LoadState::Failed {
+            eprintln!("could not load the wave table {}", DEFAULT_WAVE_TABLE);
+            std::process::exit(1);
+        }
+    }
+
+    app.insert_resource(NextState(GameState::InGame));
+
+    let mut dead = false;
+    while app.world.resource::<RunStats>().elapsed.elapsed_secs() < MAX_GAME_SECONDS {
+        step(&mut app);
+
+        if app.world.resource::<CurrentState<GameState>>().0 == GameState::GameOver {
+            dead = true;
+            break;
+        }
+    }
+
+    let stats = app.world.resource::<RunStats>();
+    let wave = app.world.resource::<Wave>();
+    let result = ReplayResult {
+        waves: wave.current,
+        kills: stats.kills,
+        ticks: stats.ticks
+    };
+
+    GameReport {
+        seed,
+        player,
+        // The current wave is still being fought, so only the ones before it count.
+        waves_survived: wave.current.saturating_sub(1),
+        kills: stats.kills,
+        damage_dealt: stats.damage_dealt,
+        time_to_death: dead.then(|| stats.elapsed.elapsed_secs()),
+        matches_replay: expected.map(|expected| expected == result)
+    }
+}
+
+fn generate_obstacles(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    terrain: Res<Terrain>,
+    layout: Res<ObstacleLayout>
+) {
+    spawn_obstacles(&mut commands, &mut meshes, &mut materials, &layout, &terrain);
+}
+
+fn scripted_player(
+    script: Res<PlayerScript>,
+    stats: Res<RunStats>,
+    mut players: Query<&mut Player>
+) {
+    let target = match *script {
+        PlayerScript::Idle => return,
+        PlayerScript::Circle { radius, period } => {
+            let angle = TAU * stats.elapsed.elapsed_secs() / period;
+            Vec3::new(radius * angle.cos(), 0.5, radius * angle.sin())
+        }
+    };
+
+    for mut player in players.iter_mut() {
+        player.target_position = target;
+    }
+}
+
+/// Always takes the first upgrade on offer.
+fn scripted_upgrade(
+    mut commands: Commands,
+    experience: Res<Experience>,
+    mut pending: ResMut<PendingUpgrades>
+) {
+    choose_upgrade(&mut commands, &experience, &mut pending, 0);
+}
@@ -0,0 +1,266 @@
+use std::f32::consts::FRAC_PI_2;
+use bevy::{
+    prelude::*,
+};
+use bevy::input::mouse::{MouseScrollUnit, MouseWheel};
+use iyes_loopless::prelude::*;
+use noise::{NoiseFn, Perlin};
+use crate::GameState;
+use crate::health::DamageDealtEvent;
+use crate::input::{Action, ActionState};
+use crate::player::Player;
+use crate::settings::Settings;
+
+/// How quickly the camera catches up with the player, higher is stiffer.
+pub const FOLLOW_STIFFNESS: f32 = 8.0;
+/// How quickly the camera turns to a new quarter, higher is faster.
+pub const ROTATION_STIFFNESS: f32 = 10.0;
+
+/// Limits on `OrthographicProjection::scale`, smaller is closer in.
+pub const DEFAULT_ZOOM: f32 = 3.0;
+pub const MIN_ZOOM: f32 = 1.5;
+pub const MAX_ZOOM: f32 = 6.0;
+/// Share of the current zoom changed by one line of the mouse wheel.
+pub const ZOOM_STEP: f32 = 0.1;
+/// Seconds without scrolling before the zoom is saved, so a whole scroll is saved once.
+pub const ZOOM_SAVE_DELAY: f32 = 0.5;
+/// Pixels of touchpad scrolling counted as one line.
+pub const PIXELS_PER_LINE: f32 = 100.0;
+
+/// Trauma added every time a hit gets through to the player, from 0 to 1.
+pub const TRAUMA_PER_HIT: f32 = 0.4;
+/// Trauma added per point of health drained from the player, so a crowd shakes harder than one mob.
+pub const TRAUMA_PER_DRAINED: f32 = 0.5;
+/// Trauma lost per second.
+pub const TRAUMA_DECAY: f32 = 1.2;
+/// Furthest the camera is moved and rolled at full trauma.
+pub const MAX_SHAKE_OFFSET: f32 = 0.6;
+pub const MAX_SHAKE_ANGLE: f32 = 0.05;
+/// Speed the shake noise is sampled at, higher shakes faster.
+pub const SHAKE_FREQUENCY: f64 = 20.0;
+
+/// The camera looking down on the arena, trailing the player.
+#[derive(Component)]
+pub struct GameCamera {
+    /// How far back the camera sits from the player, and how much of the world it shows.
+    pub distance: f32,
+    /// Quarter turns around the player, counted without wrapping so turns always take the short way.
+    pub rotation: i32,
+    /// Shake strength from 0 to 1, the camera shakes by its square.
+    pub trauma: f32,
+    /// Point looked at, trailing the player.
+    focus: Vec3,
+    focus_velocity: Vec3,
+    yaw: f32,
+    yaw_velocity: f32,
+    noise: Perlin
+}
+
+impl GameCamera {
+    pub fn new(distance: f32) -> Self {
+        GameCamera {
+            distance,
+            rotation: 0,
+            trauma: 0.0,
+            focus: Vec3::ZERO,
+            focus_velocity: Vec3::ZERO,
+            yaw: 0.0,
+            yaw_velocity: 0.0,
+            noise: Perlin::new(0)
+        }
+    }
+
+    pub fn add_trauma(&mut self, trauma: f32) {
+        self.trauma = (self.trauma + trauma).min(1.0);
+    }
+
+    /// Directions on the ground pointing up and right on the screen, once the camera has finished
+    /// turning. Unlike the camera's transform they are steady through the turn and the shake.
+    pub fn ground_axes(&self) -> (Vec3, Vec3) {
+        let turn = Quat::from_rotation_y(self.rotation as f32 * FRAC_PI_2);
+
+        (
+            turn * Vec3::new(-1., 0., -1.).normalize(),
+            turn * Vec3::new(1., 0., -1.).normalize()
+        )
+    }
+
+    /// Where the camera sits relative to the point it looks at.
+    fn offset(&self) -> Vec3 {
+        Quat::from_rotation_y(self.yaw) * Vec3::new(self.distance / 1.5, self.distance, self.distance / 1.5)
+    }
+}
+
+/// Step `current` towards `target` along a critically damped spring, which settles as fast as it
+/// can without overshooting. Stable for any `dt`.
+fn critically_damped<T>(
+    current: T,
+    target: T,
+    velocity: &mut T,
+    stiffness: f32,
+    dt: f32
+) -> T
+where
+    T: Copy + std::ops::Add<Output = T> + std::ops::Sub<Output = T> + std::ops::Mul<f32, Output = T>
+{
+    let x = stiffness * dt;
+    let decay = 1.0 / (1.0 + x + 0.48 * x * x + 0.235 * x * x * x);
+
+    let change = current - target;
+    let temp = (*velocity + change * stiffness) * dt;
+    *velocity = (*velocity - temp * stiffness) * decay;
+
+    target + (change + temp) * decay
+}
+
+/// Moves the `GameCamera` every frame, after the simulation has moved the player.
+pub struct CameraPlugin;
+impl Plugin for CameraPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system_set(
+            ConditionSet::new()
+                .run_in_state(GameState::InGame)
+                .with_system(zoom_camera)
+                .with_system(rotate_camera)
+                .with_system(shake_on_hit)
+                .into()
+        ).add_exit_system(GameState::InGame, save_zoom)
+        .add_system(follow_player);
+    }
+}
+
+/// Zoom with the mouse wheel, saving the zoom in the `Settings` once the scrolling stops.
+fn zoom_camera(
+    time: Res<Time>,
+    mut wheel: EventReader<MouseWheel>,
+    mut since_scroll: Local<Option<f32>>,
+    mut settings: ResMut<Settings>,
+    mut cameras: Query<&mut Projection, With<GameCamera>>
+) {
+    let lines: f32 = wheel.iter()
+        .map(|event| match event.unit {
+            MouseScrollUnit::Line => event.y,
+            MouseScrollUnit::Pixel => event.y / PIXELS_PER_LINE
+        })
+        .sum();
+
+    if lines != 0. {
+        for mut projection in cameras.iter_mut() {
+            if let Projection::Orthographic(orthographic) = &mut *projection {
+                // Scrolling up zooms in.
+                orthographic.scale = (orthographic.scale * (1.0 - ZOOM_STEP).powf(lines)).clamp(MIN_ZOOM, MAX_ZOOM);
+            }
+        }
+
+        *since_scroll = Some(0.0);
+        return;
+    }
+
+    if let Some(since) = since_scroll.as_mut() {
+        *since += time.delta_seconds();
+
+        if *since >= ZOOM_SAVE_DELAY {
+            *since_scroll = None;
+            for projection in cameras.iter() {
+                store_zoom(&mut settings, projection);
+            }
+        }
+    }
+}
+
+/// Save the zoom on leaving the run, in case it is left before the scrolling has settled.
+fn save_zoom(
+    mut settings: ResMut<Settings>,
+    cameras: Query<&Projection, With<GameCamera>>
+) {
+    for projection in cameras.iter() {
+        store_zoom(&mut settings, projection);
+    }
+}
+
+fn store_zoom(
+    settings: &mut ResMut<Settings>,
+    projection: &Projection
+) {
+    if let Projection::Orthographic(orthographic) = projection {
+        if settings.zoom != orthographic.scale {
+            settings.zoom = orthographic.scale;
+            settings.save();
+        }
+    }
+}
+
+fn rotate_camera(
+    actions: Res<ActionState>,
+    mut cameras: Query<&mut GameCamera>
+) {
+    for mut camera in cameras.iter_mut() {
+        if actions.just_pressed(Action::RotateLeft) {
+            camera.rotation -= 1;
+        }
+        if actions.just_pressed(Action::RotateRight) {
+            camera.rotation += 1;
+        }
+    }
+}
+
+/// Add trauma for every hit that gets through to the player. Drains wear the player down a little
+/// every tick, so they add trauma by the health taken rather than per event.
+fn shake_on_hit(
+    mut dealt: EventReader<DamageDealtEvent>,
+    players: Query<(), With<Player>>,
+    mut cameras: Query<&mut GameCamera>
+) {
+    for hit in dealt.iter() {
+        if !players.contains(hit.target) {
+            continue;
+        }
+
+        let trauma = if hit.kind.is_drain() {
+            hit.amount * TRAUMA_PER_DRAINED
+        } else {
+            TRAUMA_PER_HIT
+        };
+
+        for mut camera in cameras.iter_mut() {
+            camera.add_trauma(trauma);
+        }
+    }
+}
+
+fn follow_player(
+    time: Res<Time>,
+    players: Query<&Transform, (With<Player>, Without<GameCamera>)>,
+    mut cameras: Query<(&mut Transform, &mut GameCamera)>
+) {
+    let dt = time.delta_seconds();
+    let target = players.get_single().map_or(Vec3::ZERO, |transform| transform.translation);
+
+    for (mut transform, mut camera) in cameras.iter_mut() {
+        let camera = &mut *camera;
+
+        camera.focus = critically_damped(camera.focus, target, &mut camera.focus_velocity, FOLLOW_STIFFNESS, dt);
+        camera.yaw = critically_damped(
+            camera.yaw,
+            camera.rotation as f32 * FRAC_PI_2,
+            &mut camera.yaw_velocity,
+            ROTATION_STIFFNESS,
+            dt
+        );
+        camera.trauma = (camera.trauma - TRAUMA_DECAY * dt).max(0.0);
+
+        *transform = Transform::from_translation(camera.focus + camera.offset())
+            .looking_at(camera.focus, Vec3::Y);
+
+        // Shake across the screen and roll, each driven by its own slice of the noise.
+        let shake = camera.trauma * camera.trauma;
+        if shake > 0. {
+            let t = time.elapsed_seconds_f64() * SHAKE_FREQUENCY;
+            let sample = |slice: f64| camera.noise.get([t, slice]) as f32;
+
+            let offset = transform.right() * sample(0.0) + transform.up() * sample(10.0);
+            transform.translation += offset * shake * MAX_SHAKE_OFFSET;
+            transform.rotate_local_z(sample(20.0) * shake * MAX_SHAKE_ANGLE);
+        }
+    }
+}
@@ -0,0 +1,222 @@
+use bevy::{
+    prelude::*,
+};
+use crate::mob::{MAX_MOB_SIZE, Mob};
+use crate::obstacle::{Obstacle, ObstacleGrid};
+use crate::player::{Player, PLAYER_MASS};
+use crate::simulation::PLAYER_SIZE;
+use crate::spatial::SpatialGrid;
+
+/// Bounciness of a collision, 0 is perfectly inelastic and 1 is perfectly elastic.
+pub const RESTITUTION: f32 = 0.2;
+
+/// Passes over the contacts per tick, more passes settle dense crowds better.
+pub const COLLISION_ITERATIONS: usize = 2;
+
+/// Separate two overlapping circles on the ground plane, splitting the correction and the
+/// exchanged impulse by inverse mass. Returns whether they were touching.
+pub fn resolve_contact(
+    pos_a: &mut Vec3,
+    vel_a: &mut Vec3,
+    inv_mass_a: f32,
+    pos_b: &mut Vec3,
+    vel_b: &mut Vec3,
+    inv_mass_b: f32,
+    min_distance: f32
+) -> bool {
+    let mut offset = *pos_b - *pos_a;
+    offset.y = 0.;
+
+    let distance_squared = offset.length_squared();
+    if distance_squared >= min_distance * min_distance {
+        return false;
+    }
+
+    let inv_mass_total = inv_mass_a + inv_mass_b;
+    if inv_mass_total <= 0. {
+        return true;
+    }
+
+    // Two bodies exactly on top of each other still need a direction to be pushed apart in.
+    let distance = distance_squared.sqrt();
+    let normal = if distance > f32::EPSILON { offset / distance } else { Vec3::X };
+
+    // Positional correction, so bodies never end a tick stacked inside each other.
+    let correction = normal * (min_distance - distance) / inv_mass_total;
+    *pos_a -= correction * inv_mass_a;
+    *pos_b += correction * inv_mass_b;
+
+    // Impulse exchange, only while the bodies are still closing in on each other.
+    let closing_speed = (*vel_b - *vel_a).dot(normal);
+    if closing_speed < 0. {
+        let impulse = normal * -(1.0 + RESTITUTION) * closing_speed / inv_mass_total;
+        *vel_a -= impulse * inv_mass_a;
+        *vel_b += impulse * inv_mass_b;
+    }
+
+    true
+}
+
+/// Push a circle of `radius` out of a static obstacle, removing the velocity into it.
+/// Returns whether they were touching.
+pub fn resolve_obstacle_contact(
+    obstacle: &Obstacle,
+    pos: &mut Vec3,
+    vel: &mut Vec3,
+    radius: f32
+) -> bool {
+    let (distance, normal) = obstacle.distance(*pos);
+    if distance >= radius {
+        return false;
+    }
+
+    *pos += normal * (radius - distance);
+
+    let closing_speed = vel.dot(normal);
+    if closing_speed < 0. {
+        *vel -= normal * (1.0 + RESTITUTION) * closing_speed;
+    }
+
+    true
+}
+
+pub fn resolve_collisions(
+    grid: Res<SpatialGrid>,
+    obstacles: Res<ObstacleGrid>,
+    mut mobs: Query<(Entity, &mut Transform, &mut Mob), With<Mob>>,
+    mut players: Query<(&mut Transform, &mut Player), (Without<Mob>, With<Player>)>
+) {
+    let entities: Vec<Entity> = mobs.iter().map(|(entity, _, _)| entity).collect();
+
+    for _ in 0..COLLISION_ITERATIONS {
+        // Mob against mob, each pair is only visited from its lower entity.
+        for &entity in entities.iter() {
+            let position = match mobs.get(entity) {
+                Ok((_, _, mob)) => mob.pos,
+                Err(_) => continue,
+            };
+
+            for (other, _) in grid.neighbours(position, MAX_MOB_SIZE) {
+                if *other <= entity {
+                    continue;
+                }
+
+                if let Ok([(_, _, mut mob1), (_, _, mut mob2)]) = mobs.get_many_mut([entity, *other]) {
+                    let (inv_mass1, inv_mass2) = (1.0 / mob1.mass, 1.0 / mob2.mass);
+                    let min_distance = (mob1.size + mob2.size) * 0.5;
+                    let (mob1, mob2) = (&mut *mob1, &mut *mob2);
+
+                    resolve_contact(
+                        &mut mob1.pos, &mut mob1.vel, inv_mass1,
+                        &mut mob2.pos, &mut mob2.vel, inv_mass2,
+                        min_distance
+                    );
+                }
+            }
+        }
+
+        // Mobs against the player.
+        for (_, mut player) in players.iter_mut() {
+            let player = &mut *player;
+
+            for (other, _) in grid.neighbours(player.pos, (MAX_MOB_SIZE + PLAYER_SIZE) * 0.5) {
+                if let Ok((_, _, mut mob)) = mobs.get_mut(*other) {
+                    // Jumping over a mob doesn't touch it.
+                    if player.clears(mob.pos.y + mob.size * 0.5) {
+                        continue;
+                    }
+
+                    let inv_mass = 1.0 / mob.mass;
+                    let min_distance = (mob.size + PLAYER_SIZE) * 0.5;
+                    let mob = &mut *mob;
+
+                    resolve_contact(
+                        &mut player.pos, &mut player.vel, 1.0 / PLAYER_MASS,
+                        &mut mob.pos, &mut mob.vel, inv_mass,
+                        min_distance
+                    );
+                }
+            }
+        }
+
+        // Obstacles last, so nothing is left pushed inside one.
+        for (_, _, mut mob) in mobs.iter_mut() {
+            let mob = &mut *mob;
+            for obstacle in obstacles.near(mob.pos, mob.size * 0.5) {
+                resolve_obstacle_contact(obstacle, &mut mob.pos, &mut mob.vel, mob.size * 0.5);
+            }
+        }
+
+        for (_, mut player) in players.iter_mut() {
+            let player = &mut *player;
+            for obstacle in obstacles.near(player.pos, PLAYER_SIZE * 0.5) {
+                resolve_obstacle_contact(obstacle, &mut player.pos, &mut player.vel, PLAYER_SIZE * 0.5);
+            }
+        }
+    }
+
+    for (_, mut transform, mob) in mobs.iter_mut() {
+        transform.translation = mob.pos;
+    }
+
+    for (mut transform, player) in players.iter_mut() {
+        transform.translation = player.pos;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::obstacle::ObstacleShape;
+
+    #[test]
+    fn overlapping_bodies_are_pushed_apart_by_inverse_mass() {
+        let (mut pos_a, mut vel_a) = (Vec3::ZERO, Vec3::ZERO);
+        let (mut pos_b, mut vel_b) = (Vec3::new(0.5, 0., 0.), Vec3::ZERO);
+
+        assert!(resolve_contact(&mut pos_a, &mut vel_a, 1.0, &mut pos_b, &mut vel_b, 1.0, 1.0));
+        assert!((pos_a.x + 0.25).abs() < 1e-5);
+        assert!((pos_b.x - 0.75).abs() < 1e-5);
+    }
+
+    #[test]
+    fn immovable_bodies_stay_put() {
+        let (mut pos_a, mut vel_a) = (Vec3::ZERO, Vec3::ZERO);
+        let (mut pos_b, mut vel_b) = (Vec3::new(0.5, 0., 0.), Vec3::ZERO);
+
+        resolve_contact(&mut pos_a, &mut vel_a, 1.0, &mut pos_b, &mut vel_b, 0.0, 1.0);
+        assert!((pos_a.x + 0.5).abs() < 1e-5);
+        assert_eq!(pos_b, Vec3::new(0.5, 0., 0.));
+    }
+
+    #[test]
+    fn closing_bodies_bounce_off_each_other() {
+        let (mut pos_a, mut vel_a) = (Vec3::ZERO, Vec3::X);
+        let (mut pos_b, mut vel_b) = (Vec3::new(0.9, 0., 0.), Vec3::NEG_X);
+
+        resolve_contact(&mut pos_a, &mut vel_a, 1.0, &mut pos_b, &mut vel_b, 1.0, 1.0);
+        assert!((vel_a.x + RESTITUTION).abs() < 1e-5);
+        assert!((vel_b.x - RESTITUTION).abs() < 1e-5);
+    }
+
+    #[test]
+    fn contacts_only_count_on_the_ground_plane() {
+        let (mut pos_a, mut vel_a) = (Vec3::ZERO, Vec3::ZERO);
+        let (mut pos_b, mut vel_b) = (Vec3::new(2.0, 0.5, 0.), Vec3::ZERO);
+
+        assert!(!resolve_contact(&mut pos_a, &mut vel_a, 1.0, &mut pos_b, &mut vel_b, 1.0, 1.0));
+
+        pos_b = Vec3::new(0.5, 10.0, 0.);
+        assert!(resolve_contact(&mut pos_a, &mut vel_a, 1.0, &mut pos_b, &mut vel_b, 1.0, 1.0));
+    }
+
+    #[test]
+    fn obstacles_push_out_and_stop_velocity_into_them() {
+        let obstacle = Obstacle { pos: Vec3::ZERO, shape: ObstacleShape::Pillar { radius: 1.0 } };
+        let (mut pos, mut vel) = (Vec3::new(1.2, 0., 0.), Vec3::NEG_X);
+
+        assert!(resolve_obstacle_contact(&obstacle, &mut pos, &mut vel, 0.5));
+        assert!((pos.x - 1.5).abs() < 1e-5);
+        assert!(vel.x >= 0.);
+    }
+}
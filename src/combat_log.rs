@@ -0,0 +1,144 @@
+use std::fs::{File, OpenOptions};
+use std::io::{LineWriter, Write};
+use std::path::Path;
+use bevy::{
+    prelude::*,
+};
+use serde::Serialize;
+use crate::health::{DamageDealtEvent, DamageKind};
+use crate::mob::Mob;
+use crate::player::Player;
+use crate::rng::SimulationRng;
+use crate::simulation::RunStats;
+
+/// Command line flag to write every hit and death to a file, e.g. `--combat-log combat.jsonl`.
+pub const COMBAT_LOG_ARG: &str = "--combat-log";
+
+/// One line of the combat log.
+#[derive(Serialize)]
+struct CombatLogEntry {
+    seed: u64,
+    tick: u64,
+    event: &'static str,
+    source: Option<String>,
+    target: String,
+    kind: DamageKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    amount: Option<f32>
+}
+
+/// Every hit and death of a run, written as one JSON object per line for balancing analysis.
+/// Runs are appended to the end of the file, told apart by their seed.
+#[derive(Resource)]
+pub struct CombatLog {
+    file: LineWriter<File>
+}
+
+impl CombatLog {
+    pub fn open(
+        path: &Path
+    ) -> Result<CombatLog, String> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+
+        Ok(CombatLog { file: LineWriter::new(file) })
+    }
+
+    /// Open the combat log named on the command line, if there is one.
+    pub fn from_args() -> Option<CombatLog> {
+        let args: Vec<String> = std::env::args().collect();
+        let path = args.iter().position(|arg| arg == COMBAT_LOG_ARG).and_then(|i| args.get(i + 1))?;
+
+        match CombatLog::open(Path::new(path)) {
+            Ok(log) => Some(log),
+            Err(e) => {
+                eprintln!("could not open combat log {}", e);
+                None
+            }
+        }
+    }
+
+    pub fn damage(
+        &mut self,
+        seed: u64,
+        tick: u64,
+        hit: &DamageDealtEvent,
+        names: &Query<(Option<&Mob>, Option<&Player>)>
+    ) {
+        self.write(seed, tick, "damage", hit, Some(hit.amount), names);
+    }
+
+    pub fn death(
+        &mut self,
+        seed: u64,
+        tick: u64,
+        hit: &DamageDealtEvent,
+        names: &Query<(Option<&Mob>, Option<&Player>)>
+    ) {
+        self.write(seed, tick, "death", hit, None, names);
+    }
+
+    fn write(
+        &mut self,
+        seed: u64,
+        tick: u64,
+        event: &'static str,
+        hit: &DamageDealtEvent,
+        amount: Option<f32>,
+        names: &Query<(Option<&Mob>, Option<&Player>)>
+    ) {
+        let entry = CombatLogEntry {
+            seed,
+            tick,
+            event,
+            source: hit.source.map(|source| name(source, names)),
+            target: name(hit.target, names),
+            kind: hit.kind,
+            amount
+        };
+
+        let line = serde_json::to_string(&entry).unwrap();
+        if let Err(e) = writeln!(self.file, "{}", line) {
+            warn!("could not write to combat log {}", e);
+        }
+    }
+}
+
+/// Write down the damage dealt this tick, when a combat log was asked for.
+pub fn write_combat_log(
+    rng: Res<SimulationRng>,
+    stats: Res<RunStats>,
+    log: Option<ResMut<CombatLog>>,
+    mut dealt: EventReader<DamageDealtEvent>,
+    names: Query<(Option<&Mob>, Option<&Player>)>
+) {
+    let mut log = match log {
+        Some(log) => log,
+        None => return
+    };
+
+    for hit in dealt.iter() {
+        // Drains land every tick, so only the ones that kill are worth writing down.
+        if !hit.kind.is_drain() || hit.killed {
+            log.damage(rng.seed, stats.ticks, hit, &names);
+        }
+        if hit.killed {
+            log.death(rng.seed, stats.ticks, hit, &names);
+        }
+    }
+}
+
+/// `player`, or the archetype of a mob followed by its entity index, e.g. `grunt#42`.
+fn name(
+    entity: Entity,
+    names: &Query<(Option<&Mob>, Option<&Player>)>
+) -> String {
+    match names.get(entity) {
+        Ok((_, Some(_))) => "player".to_string(),
+        Ok((Some(mob), _)) => format!("{}#{}", mob.archetype, entity.index()),
+        _ => format!("#{}", entity.index())
+    }
+}
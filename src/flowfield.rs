@@ -0,0 +1,345 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use bevy::{
+    prelude::*,
+};
+use crate::arena::ArenaBounds;
+use crate::obstacle::Obstacle;
+use crate::player::Player;
+
+/// Side length of a flow field cell.
+pub const FLOW_CELL_SIZE: f32 = 1.0;
+/// Clearance kept around obstacles, so paths don't graze their edges.
+pub const FLOW_OBSTACLE_MARGIN: f32 = 0.5;
+/// Mobs closer to the player than this head straight for them instead of following the field.
+pub const FLOW_DIRECT_DISTANCE: f32 = 3.0;
+
+/// Path costs between neighbouring cells, integers so the field comes out the same everywhere.
+const STRAIGHT_COST: u32 = 10;
+const DIAGONAL_COST: u32 = 14;
+
+const NEIGHBOURS: [(i32, i32); 8] = [
+    (1, 0), (-1, 0), (0, 1), (0, -1),
+    (1, 1), (1, -1), (-1, 1), (-1, -1),
+];
+
+/// Direction to walk in from every cell of the arena to reach the player around obstacles.
+///
+/// Rebuilt only when the player moves to another cell, so looking up a direction costs a mob
+/// the same no matter how many others there are.
+#[derive(Resource)]
+pub struct FlowField {
+    origin: Vec2,
+    cell_size: f32,
+    width: i32,
+    height: i32,
+    blocked: Vec<bool>,
+    cost: Vec<u32>,
+    directions: Vec<Vec3>,
+    target: Option<(i32, i32)>
+}
+
+impl FromWorld for FlowField {
+    fn from_world(world: &mut World) -> Self {
+        let bounds = *world.get_resource_or_insert_with(ArenaBounds::default);
+
+        FlowField::new(bounds.min, bounds.max, FLOW_CELL_SIZE)
+    }
+}
+
+impl FlowField {
+    pub fn new(
+        min: Vec2,
+        max: Vec2,
+        cell_size: f32
+    ) -> Self {
+        let width = ((max.x - min.x) / cell_size).ceil() as i32;
+        let height = ((max.y - min.y) / cell_size).ceil() as i32;
+        let cells = (width * height) as usize;
+
+        FlowField {
+            origin: min,
+            cell_size,
+            width,
+            height,
+            blocked: vec![false; cells],
+            cost: vec![u32::MAX; cells],
+            directions: vec![Vec3::ZERO; cells],
+            target: None
+        }
+    }
+
+    /// Cell containing `position`, or `None` outside the field.
+    pub fn cell(
+        &self,
+        position: Vec3
+    ) -> Option<(i32, i32)> {
+        let x = ((position.x - self.origin.x) / self.cell_size).floor() as i32;
+        let z = ((position.z - self.origin.y) / self.cell_size).floor() as i32;
+
+        self.index(x, z).map(|_| (x, z))
+    }
+
+    fn index(
+        &self,
+        x: i32,
+        z: i32
+    ) -> Option<usize> {
+        if x < 0 || z < 0 || x >= self.width || z >= self.height {
+            return None;
+        }
+
+        Some((z * self.width + x) as usize)
+    }
+
+    fn centre(
+        &self,
+        x: i32,
+        z: i32
+    ) -> Vec3 {
+        Vec3::new(
+            self.origin.x + (x as f32 + 0.5) * self.cell_size,
+            0.,
+            self.origin.y + (z as f32 + 0.5) * self.cell_size
+        )
+    }
+
+    /// Mark the cells covered by obstacles. The field has to be recomputed afterwards.
+    pub fn set_obstacles(
+        &mut self,
+        obstacles: &[&Obstacle]
+    ) {
+        let clearance = self.cell_size * std::f32::consts::FRAC_1_SQRT_2 + FLOW_OBSTACLE_MARGIN;
+
+        for z in 0..self.height {
+            for x in 0..self.width {
+                let centre = self.centre(x, z);
+                let blocked = obstacles.iter().any(|obstacle| obstacle.overlaps(centre, clearance));
+                self.blocked[(z * self.width + x) as usize] = blocked;
+            }
+        }
+
+        self.target = None;
+    }
+
+    pub fn target(&self) -> Option<(i32, i32)> {
+        self.target
+    }
+
+    /// Recompute path costs and directions towards the `target` cell with Dijkstra's algorithm.
+    pub fn compute(
+        &mut self,
+        target: (i32, i32)
+    ) {
+        self.target = Some(target);
+        self.cost.fill(u32::MAX);
+        self.directions.fill(Vec3::ZERO);
+
+        let start = match self.index(target.0, target.1) {
+            Some(start) => start,
+            None => return
+        };
+
+        // The player's own cell is always the goal, even when pressed up against an obstacle.
+        let mut open = BinaryHeap::new();
+        self.cost[start] = 0;
+        open.push(Reverse((0, target.0, target.1)));
+
+        while let Some(Reverse((cost, x, z))) = open.pop() {
+            if cost > self.cost[(z * self.width + x) as usize] {
+                continue;
+            }
+
+            for (dx, dz) in NEIGHBOURS {
+                let (nx, nz) = (x + dx, z + dz);
+                let next = match self.index(nx, nz) {
+                    Some(next) if self.passable(x, z, dx, dz) => next,
+                    _ => continue
+                };
+
+                let step = if dx != 0 && dz != 0 { DIAGONAL_COST } else { STRAIGHT_COST };
+                if cost + step < self.cost[next] {
+                    self.cost[next] = cost + step;
+                    open.push(Reverse((cost + step, nx, nz)));
+                }
+            }
+        }
+
+        // Each cell points at its cheapest neighbour.
+        for z in 0..self.height {
+            for x in 0..self.width {
+                let index = (z * self.width + x) as usize;
+                if self.cost[index] == u32::MAX || index == start {
+                    continue;
+                }
+
+                let mut best = self.cost[index];
+                for (dx, dz) in NEIGHBOURS {
+                    let next = match self.index(x + dx, z + dz) {
+                        Some(next) if self.passable(x, z, dx, dz) => next,
+                        _ => continue
+                    };
+
+                    if self.cost[next] < best {
+                        best = self.cost[next];
+                        self.directions[index] = Vec3::new(dx as f32, 0., dz as f32).normalize();
+                    }
+                }
+            }
+        }
+    }
+
+    /// Whether a step from cell (x, z) by (dx, dz) is open. Diagonal steps can't cut the corner
+    /// of a blocked cell.
+    fn passable(
+        &self,
+        x: i32,
+        z: i32,
+        dx: i32,
+        dz: i32
+    ) -> bool {
+        let open = |x: i32, z: i32| self.index(x, z).map_or(false, |index| !self.blocked[index]);
+
+        open(x + dx, z + dz) && (dx == 0 || dz == 0 || (open(x + dx, z) && open(x, z + dz)))
+    }
+
+    /// Direction to walk in from `position`, or `None` in the target cell, in blocked cells and
+    /// wherever the target can't be reached from.
+    pub fn direction(
+        &self,
+        position: Vec3
+    ) -> Option<Vec3> {
+        let (x, z) = self.cell(position)?;
+        let direction = self.directions[(z * self.width + x) as usize];
+
+        if direction == Vec3::ZERO { None } else { Some(direction) }
+    }
+}
+
+/// Point the field at the player again whenever they reach another cell, and re-mark the
+/// obstacles when they change.
+pub fn update_flow_field(
+    mut field: ResMut<FlowField>,
+    obstacles: Query<&Obstacle>,
+    changed: Query<(), Changed<Obstacle>>,
+    players: Query<&Player>
+) {
+    if !changed.is_empty() {
+        let obstacles: Vec<&Obstacle> = obstacles.iter().collect();
+        field.set_obstacles(&obstacles);
+    }
+
+    let cell = match players.iter().next().and_then(|player| field.cell(player.pos)) {
+        Some(cell) => cell,
+        None => return
+    };
+
+    if field.target() != Some(cell) {
+        field.compute(cell);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::obstacle::ObstacleShape;
+
+    fn field() -> FlowField {
+        FlowField::new(Vec2::ZERO, Vec2::splat(10.0), 1.0)
+    }
+
+    /// Follow the field from `start` one cell at a time, returning the cells passed through.
+    fn walk(
+        field: &FlowField,
+        start: Vec3
+    ) -> Vec<Vec3> {
+        let mut path = vec![start];
+        let mut position = start;
+
+        while let Some(direction) = field.direction(position) {
+            position += Vec3::new(direction.x.round(), 0., direction.z.round());
+            path.push(position);
+            assert!(path.len() < 100, "the field goes round in circles");
+        }
+
+        path
+    }
+
+    #[test]
+    fn open_ground_leads_straight_to_the_target() {
+        let mut field = field();
+        field.compute((5, 5));
+
+        assert_eq!(field.direction(Vec3::new(2.5, 0., 5.5)), Some(Vec3::X));
+        assert_eq!(field.direction(Vec3::new(5.5, 0., 5.5)), None);
+        assert_eq!(field.cell(*walk(&field, Vec3::new(0.5, 0., 9.5)).last().unwrap()), Some((5, 5)));
+    }
+
+    #[test]
+    fn paths_go_around_obstacles() {
+        let wall = Obstacle {
+            pos: Vec3::new(5.0, 0., 5.0),
+            shape: ObstacleShape::Wall { half_extents: Vec2::new(0.5, 3.0) }
+        };
+        let mut field = field();
+        field.set_obstacles(&[&wall]);
+        field.compute((8, 5));
+
+        let path = walk(&field, Vec3::new(1.5, 0., 5.5));
+        assert_eq!(field.cell(*path.last().unwrap()), Some((8, 5)));
+        assert!(path.iter().all(|position| !wall.overlaps(*position, 0.)));
+    }
+
+    #[test]
+    fn walled_off_cells_have_no_direction() {
+        let wall = Obstacle {
+            pos: Vec3::new(5.0, 0., 5.0),
+            shape: ObstacleShape::Wall { half_extents: Vec2::new(0.5, 10.0) }
+        };
+        let mut field = field();
+        field.set_obstacles(&[&wall]);
+        field.compute((8, 5));
+
+        assert_eq!(field.direction(Vec3::new(1.5, 0., 5.5)), None);
+        assert!(field.direction(Vec3::new(9.5, 0., 9.5)).is_some());
+    }
+    #[test]
+    fn cells_are_counted_from_the_lowest_corner() {
+        let field = FlowField::new(Vec2::splat(-10.0), Vec2::splat(10.0), 2.0);
+
+        assert_eq!(field.cell(Vec3::new(-10.0, 5.0, -9.0)), Some((0, 0)));
+        assert_eq!(field.cell(Vec3::new(9.9, 0., 0.5)), Some((9, 5)));
+        assert_eq!(field.cell(Vec3::new(10.1, 0., 0.)), None);
+        assert_eq!(field.cell(Vec3::new(0., 0., -10.1)), None);
+    }
+
+    #[test]
+    fn diagonal_steps_never_cut_a_blocked_corner() {
+        let mut field = field();
+        let corner = field.index(5, 4).unwrap();
+        field.blocked[corner] = true;
+        field.compute((5, 5));
+
+        // Straight up and then across, rather than squeezing past the blocked cell.
+        assert_eq!(field.direction(Vec3::new(4.5, 0., 4.5)), Some(Vec3::Z));
+        assert_eq!(field.direction(Vec3::new(5.5, 0., 4.5)), None);
+    }
+
+    #[test]
+    fn targets_outside_the_field_lead_nowhere() {
+        let mut field = field();
+        field.compute((20, 5));
+
+        assert_eq!(field.target(), Some((20, 5)));
+        assert_eq!(field.direction(Vec3::new(5.5, 0., 5.5)), None);
+    }
+
+    #[test]
+    fn changing_the_obstacles_asks_for_a_recompute() {
+        let mut field = field();
+        field.compute((5, 5));
+        field.set_obstacles(&[]);
+
+        assert_eq!(field.target(), None);
+    }
+}
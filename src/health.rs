@@ -0,0 +1,369 @@
+use std::time::Duration;
+use bevy::{
+    prelude::*,
+};
+use bevy::time::Stopwatch;
+use iyes_loopless::prelude::*;
+use serde::Serialize;
+use crate::GameState;
+use crate::player::Player;
+use crate::weapon::WeaponKind;
+
+/// Share of a hit that always gets through, however much armor there is.
+pub const MIN_DAMAGE_FRACTION: f32 = 0.2;
+
+/// Health of the player or a mob.
+#[derive(Component, Clone, Debug)]
+pub struct Health {
+    pub current: f32,
+    pub max: f32,
+    /// Health regained per second, up to `max`.
+    pub regen: f32,
+    /// Taken off every hit.
+    pub armor: f32,
+    /// Seconds after a hit during which further hits are ignored.
+    pub invulnerability: f32,
+    since_hit: Stopwatch,
+    dead: bool
+}
+
+impl Health {
+    pub fn new(max: f32) -> Self {
+        // Starts out with the window long over, so the first hit always lands.
+        let mut since_hit = Stopwatch::new();
+        since_hit.set_elapsed(Duration::from_secs(3600));
+
+        Health {
+            current: max,
+            max,
+            regen: 0.0,
+            armor: 0.0,
+            invulnerability: 0.0,
+            since_hit,
+            dead: false
+        }
+    }
+
+    pub fn with_regen(
+        mut self,
+        regen: f32
+    ) -> Self {
+        self.regen = regen;
+        self
+    }
+
+    pub fn with_armor(
+        mut self,
+        armor: f32
+    ) -> Self {
+        self.armor = armor;
+        self
+    }
+
+    pub fn with_invulnerability(
+        mut self,
+        invulnerability: f32
+    ) -> Self {
+        self.invulnerability = invulnerability;
+        self
+    }
+
+    pub fn is_dead(&self) -> bool {
+        self.current <= 0.
+    }
+
+    pub fn is_invulnerable(&self) -> bool {
+        self.since_hit.elapsed_secs() < self.invulnerability
+    }
+
+    /// Seconds since the last hit that landed.
+    pub fn since_hit(&self) -> f32 {
+        self.since_hit.elapsed_secs()
+    }
+
+    /// Take a hit, reduced by armor and ignored while invulnerable. Returns the damage dealt.
+    pub fn damage(
+        &mut self,
+        amount: f32
+    ) -> f32 {
+        if self.is_invulnerable() || self.is_dead() {
+            return 0.0;
+        }
+
+        let dealt = (amount - self.armor).max(amount * MIN_DAMAGE_FRACTION);
+        self.current -= dealt;
+        self.since_hit.reset();
+
+        dealt
+    }
+
+    /// Lose health over time, which neither armor nor invulnerability stops.
+    pub fn drain(
+        &mut self,
+        amount: f32
+    ) -> f32 {
+        if self.is_dead() {
+            return 0.0;
+        }
+
+        self.current -= amount;
+        amount
+    }
+
+    pub fn heal(
+        &mut self,
+        amount: f32
+    ) {
+        if !self.is_dead() {
+            self.current = (self.current + amount).min(self.max);
+        }
+    }
+}
+
+/// What a hit came from.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DamageKind {
+    /// A mob touching the player, drained every tick they touch.
+    Contact,
+    /// A bolt fired by a ranged mob.
+    Bolt,
+    /// One of the player's weapons.
+    Weapon(WeaponKind),
+    /// Standing outside the arena bounds.
+    Arena,
+}
+
+impl DamageKind {
+    /// Whether hits of this kind are taken in full, ignoring armor and invulnerability.
+    pub fn is_drain(&self) -> bool {
+        matches!(self, DamageKind::Contact | DamageKind::Arena)
+    }
+}
+
+/// Sent by anything that wants to hurt something with `Health`. The damage is dealt once the
+/// tick's movement is done, in the order the events were sent.
+pub struct DamageEvent {
+    /// The mob or player the hit came from, if any.
+    pub source: Option<Entity>,
+    pub target: Entity,
+    /// Damage before armor.
+    pub amount: f32,
+    pub kind: DamageKind
+}
+
+/// Sent for every hit that got through, once armor has been taken off, for anything that reacts
+/// to damage actually being done.
+pub struct DamageDealtEvent {
+    pub source: Option<Entity>,
+    pub target: Entity,
+    /// Damage after armor, never zero.
+    pub amount: f32,
+    pub kind: DamageKind,
+    /// Whether this hit finished the target off.
+    pub killed: bool
+}
+
+/// Sent once for everything whose health runs out, along with the hit that finished it off.
+pub struct DeathEvent {
+    pub entity: Entity,
+    pub source: Option<Entity>,
+    pub kind: DamageKind
+}
+
+/// Deal the damage sent this tick, and report what got through and whatever it killed.
+pub fn apply_damage(
+    mut damage: EventReader<DamageEvent>,
+    mut dealt_events: EventWriter<DamageDealtEvent>,
+    mut deaths: EventWriter<DeathEvent>,
+    mut healths: Query<&mut Health>
+) {
+    for hit in damage.iter() {
+        let mut health = match healths.get_mut(hit.target) {
+            Ok(health) => health,
+            Err(_) => continue
+        };
+
+        let dealt = if hit.kind.is_drain() {
+            health.drain(hit.amount)
+        } else {
+            health.damage(hit.amount)
+        };
+
+        let killed = health.is_dead() && !health.dead;
+        if killed {
+            health.dead = true;
+        }
+
+        if dealt > 0. {
+            dealt_events.send(DamageDealtEvent {
+                source: hit.source,
+                target: hit.target,
+                amount: dealt,
+                kind: hit.kind,
+                killed
+            });
+        }
+        if killed {
+            deaths.send(DeathEvent { entity: hit.target, source: hit.source, kind: hit.kind });
+        }
+    }
+}
+
+/// Regenerate health and count down invulnerability.
+pub fn update_health(
+    step: Res<FixedTimestepInfo>,
+    mut healths: Query<&mut Health>
+) {
+    let dt = step.timestep().as_secs_f32();
+
+    for mut health in healths.iter_mut() {
+        health.since_hit.tick(step.timestep());
+
+        let regen = health.regen * dt;
+        health.heal(regen);
+    }
+}
+
+/// End the run when the player dies.
+pub fn player_death(
+    mut commands: Commands,
+    mut deaths: EventReader<DeathEvent>,
+    players: Query<(), With<Player>>
+) {
+    for death in deaths.iter() {
+        if players.contains(death.entity) {
+            commands.insert_resource(NextState(GameState::GameOver));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mob::{DEFAULT_ARCHETYPE, MobArchetypes};
+    use crate::player::{PLAYER_ARMOR, PLAYER_HEALTH, PLAYER_INVULNERABILITY, PLAYER_REGEN};
+
+    const TICK: f32 = 1.0 / 60.0;
+
+    /// A world with a freshly spawned player, ready for `apply_damage`.
+    fn player_world() -> (World, Entity) {
+        let mut world = World::new();
+        world.init_resource::<Events<DamageEvent>>();
+        world.init_resource::<Events<DamageDealtEvent>>();
+        world.init_resource::<Events<DeathEvent>>();
+
+        let player = world.spawn(
+            Health::new(PLAYER_HEALTH)
+                .with_regen(PLAYER_REGEN)
+                .with_armor(PLAYER_ARMOR)
+                .with_invulnerability(PLAYER_INVULNERABILITY)
+        ).id();
+
+        (world, player)
+    }
+
+    fn hit(
+        target: Entity,
+        amount: f32,
+        kind: DamageKind
+    ) -> DamageEvent {
+        DamageEvent { source: None, target, amount, kind }
+    }
+
+    #[test]
+    fn mobs_in_contact_drain_faster_than_regen_restores() {
+        let contact = MobArchetypes::default().get(DEFAULT_ARCHETYPE).unwrap().contact_damage;
+
+        for mobs in 1..=4 {
+            let (mut world, player) = player_world();
+            let mut stage = SystemStage::single_threaded().with_system(apply_damage);
+
+            // A second of ticks with every mob touching the player, regenerating as they go.
+            for _ in 0..60 {
+                world.resource_mut::<Events<DamageEvent>>()
+                    .extend((0..mobs).map(|_| hit(player, contact * TICK, DamageKind::Contact)));
+                stage.run(&mut world);
+
+                let mut health = world.get_mut::<Health>(player).unwrap();
+                let regen = health.regen * TICK;
+                health.heal(regen);
+            }
+
+            let lost = PLAYER_HEALTH - world.get::<Health>(player).unwrap().current;
+            let drained = mobs as f32 * contact;
+            assert!((lost - (drained - PLAYER_REGEN)).abs() < 1e-3, "{} mobs took {}", mobs, lost);
+            assert!((drained / PLAYER_REGEN - mobs as f32).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn contact_drains_through_invulnerability() {
+        let (mut world, player) = player_world();
+        let mut stage = SystemStage::single_threaded().with_system(apply_damage);
+
+        world.resource_mut::<Events<DamageEvent>>().extend([
+            hit(player, 5.0, DamageKind::Bolt),
+            hit(player, 5.0, DamageKind::Bolt),
+            hit(player, 1.0, DamageKind::Contact)
+        ]);
+        stage.run(&mut world);
+
+        // The second bolt lands inside the window, the contact drain doesn't care.
+        assert_eq!(world.get::<Health>(player).unwrap().current, PLAYER_HEALTH - 6.0);
+    }
+
+    #[test]
+    fn armor_reduces_damage_down_to_a_minimum() {
+        let mut health = Health::new(10.0).with_armor(2.0);
+
+        assert_eq!(health.damage(5.0), 3.0);
+        assert_eq!(health.damage(1.0), 1.0 * MIN_DAMAGE_FRACTION);
+        assert!((health.current - 6.8).abs() < 1e-5);
+    }
+
+    #[test]
+    fn hits_are_ignored_while_invulnerable() {
+        let mut health = Health::new(10.0).with_invulnerability(0.5);
+
+        assert_eq!(health.damage(3.0), 3.0);
+        assert!(health.is_invulnerable());
+        assert_eq!(health.damage(3.0), 0.0);
+
+        health.since_hit.tick(Duration::from_secs_f32(0.6));
+        assert!(!health.is_invulnerable());
+        assert_eq!(health.damage(3.0), 3.0);
+        assert_eq!(health.current, 4.0);
+    }
+
+    #[test]
+    fn drain_ignores_armor_and_invulnerability() {
+        let mut health = Health::new(10.0).with_armor(5.0).with_invulnerability(1.0);
+
+        assert_eq!(health.damage(6.0), 1.0);
+        assert_eq!(health.drain(2.0), 2.0);
+        assert_eq!(health.current, 7.0);
+    }
+
+    #[test]
+    fn heal_stops_at_max() {
+        let mut health = Health::new(10.0);
+
+        health.damage(4.0);
+        health.heal(10.0);
+        assert_eq!(health.current, 10.0);
+    }
+
+    #[test]
+    fn the_dead_take_no_damage_and_no_healing() {
+        let mut health = Health::new(10.0);
+
+        assert_eq!(health.damage(12.0), 12.0);
+        assert!(health.is_dead());
+        assert_eq!(health.damage(5.0), 0.0);
+        assert_eq!(health.drain(5.0), 0.0);
+
+        health.heal(20.0);
+        assert!(health.is_dead());
+    }
+}
@@ -0,0 +1,151 @@
+use std::fs;
+use std::path::PathBuf;
+use bevy::{
+    prelude::*,
+};
+use serde::{Deserialize, Serialize};
+
+pub const HIGH_SCORE_FILE: &str = "highscores.json";
+
+/// Used when the platform has no data directory.
+pub const FALLBACK_FOLDER: &str = "saves";
+
+/// Number of runs kept in the table.
+pub const MAX_HIGH_SCORES: usize = 10;
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub struct HighScore {
+    pub waves: usize,
+    pub time: f32,
+    pub kills: usize,
+    pub seed: u64
+}
+
+/// Best runs so far, best first, kept in sync with the file on disk.
+#[derive(Resource, Serialize, Deserialize, Default)]
+pub struct HighScores {
+    pub entries: Vec<HighScore>
+}
+
+/// `<data dir>/Amplitude`, or `saves` next to the game, where everything kept between runs goes.
+pub fn save_folder() -> PathBuf {
+    match dirs::data_dir() {
+        Some(folder) => folder.join("Amplitude"),
+        None => PathBuf::from(FALLBACK_FOLDER)
+    }
+}
+
+impl HighScores {
+    /// `<data dir>/Amplitude/highscores.json`, or `saves/highscores.json` next to the game.
+    pub fn path() -> PathBuf {
+        save_folder().join(HIGH_SCORE_FILE)
+    }
+
+    /// Read the table from disk, starting an empty one if it is missing or unreadable.
+    pub fn load() -> Self {
+        let path = HighScores::path();
+
+        match fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|e| {
+                warn!("could not read high scores from {}: {}", path.display(), e);
+                HighScores::default()
+            }),
+            Err(_) => HighScores::default()
+        }
+    }
+
+    pub fn save(&self) {
+        let path = HighScores::path();
+
+        let result = path.parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| fs::write(&path, serde_json::to_string_pretty(self).unwrap()));
+
+        if let Err(e) = result {
+            warn!("could not save high scores to {}: {}", path.display(), e);
+        }
+    }
+
+    /// Add a run to the table, returning its rank from zero if it made the cut.
+    pub fn insert(
+        &mut self,
+        score: HighScore
+    ) -> Option<usize> {
+        // More waves is better, then surviving longer breaks ties.
+        let rank = self.entries
+            .iter()
+            .position(|entry| (score.waves, score.time) > (entry.waves, entry.time))
+            .unwrap_or(self.entries.len());
+
+        if rank >= MAX_HIGH_SCORES {
+            return None;
+        }
+
+        self.entries.insert(rank, score);
+        self.entries.truncate(MAX_HIGH_SCORES);
+
+        Some(rank)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn score(
+        waves: usize,
+        time: f32
+    ) -> HighScore {
+        HighScore { waves, time, kills: 0, seed: 0 }
+    }
+
+    #[test]
+    fn more_waves_then_longer_runs_rank_higher() {
+        let mut scores = HighScores::default();
+
+        assert_eq!(scores.insert(score(3, 60.0)), Some(0));
+        assert_eq!(scores.insert(score(5, 30.0)), Some(0));
+        assert_eq!(scores.insert(score(3, 90.0)), Some(1));
+        assert_eq!(scores.insert(score(1, 10.0)), Some(3));
+
+        let waves: Vec<(usize, f32)> = scores.entries.iter().map(|entry| (entry.waves, entry.time)).collect();
+        assert_eq!(waves, vec![(5, 30.0), (3, 90.0), (3, 60.0), (1, 10.0)]);
+    }
+
+    #[test]
+    fn the_table_keeps_only_the_best_runs() {
+        let mut scores = HighScores::default();
+        for _ in 0..MAX_HIGH_SCORES {
+            scores.insert(score(5, 60.0));
+        }
+
+        assert_eq!(scores.insert(score(5, 60.0)), None);
+        assert_eq!(scores.insert(score(6, 10.0)), Some(0));
+        assert_eq!(scores.entries.len(), MAX_HIGH_SCORES);
+    }
+    #[test]
+    fn ties_go_below_the_runs_already_there() {
+        let mut scores = HighScores::default();
+        scores.insert(HighScore { kills: 1, ..score(4, 50.0) });
+
+        assert_eq!(scores.insert(HighScore { kills: 2, ..score(4, 50.0) }), Some(1));
+        assert_eq!(scores.entries[0].kills, 1);
+    }
+
+    #[test]
+    fn the_table_survives_a_round_trip_through_the_file_format() {
+        let mut scores = HighScores::default();
+        scores.insert(HighScore { waves: 7, time: 123.5, kills: 40, seed: u64::MAX });
+        scores.insert(score(2, 20.0));
+
+        let json = serde_json::to_string_pretty(&scores).unwrap();
+        let loaded: HighScores = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(loaded.entries.len(), 2);
+        assert_eq!(loaded.entries[0].waves, 7);
+        assert_eq!(loaded.entries[0].time, 123.5);
+        assert_eq!(loaded.entries[0].kills, 40);
+        assert_eq!(loaded.entries[0].seed, u64::MAX);
+        assert_eq!(loaded.entries[1].waves, 2);
+    }
+}
@@ -0,0 +1,257 @@
+use std::collections::HashMap;
+use bevy::{
+    prelude::*,
+};
+use bevy::input::InputSystem;
+use serde::{Deserialize, Serialize};
+
+/// Stick deflection below which a gamepad counts as centred.
+pub const STICK_DEAD_ZONE: f32 = 0.2;
+
+/// Everything the player can do, whatever device it comes from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Action {
+    /// Walk to the point under the cursor, following it while held.
+    MoveTo,
+    /// Walk up to the mob nearest the cursor and focus the weapons on it.
+    AttackMove,
+    /// Held while moving to add the point to the end of the waypoint queue instead.
+    Queue,
+    MoveUp,
+    MoveDown,
+    MoveLeft,
+    MoveRight,
+    Jump,
+    Dash,
+    /// Turn the camera a quarter around the player.
+    RotateLeft,
+    RotateRight,
+    Pause,
+}
+
+impl Action {
+    /// Whether using this action moves the player, and so switches them to its device.
+    pub fn is_movement(&self) -> bool {
+        !matches!(
+            self,
+            Action::Queue | Action::Jump | Action::Dash | Action::RotateLeft | Action::RotateRight | Action::Pause
+        )
+    }
+
+    pub const ALL: [Action; 12] = [
+        Action::MoveTo,
+        Action::AttackMove,
+        Action::Queue,
+        Action::MoveUp,
+        Action::MoveDown,
+        Action::MoveLeft,
+        Action::MoveRight,
+        Action::Jump,
+        Action::Dash,
+        Action::RotateLeft,
+        Action::RotateRight,
+        Action::Pause,
+    ];
+}
+
+/// A key or button an action can be bound to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Binding {
+    Key(KeyCode),
+    Mouse(MouseButton),
+    /// The button on every connected gamepad.
+    Gamepad(GamepadButtonType),
+}
+
+impl Binding {
+    /// Name shown on the settings screen.
+    pub fn describe(&self) -> String {
+        match self {
+            Binding::Key(key) => format!("{:?}", key),
+            Binding::Mouse(button) => format!("Mouse {:?}", button),
+            Binding::Gamepad(button) => format!("Pad {:?}", button)
+        }
+    }
+}
+
+/// The device the player last moved with, which decides how the player is steered.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum Device {
+    /// Click to move, the player walks to `Player::target_position`.
+    #[default]
+    Mouse,
+    /// WASD, the player moves in the held direction.
+    Keyboard,
+    /// Left stick, the player moves in the direction of the stick.
+    Gamepad,
+}
+
+/// Which keys and buttons trigger each action, kept with the rest of the `Settings`.
+#[derive(Resource, Serialize, Deserialize, Clone)]
+pub struct InputBindings {
+    pub bindings: HashMap<Action, Vec<Binding>>,
+    #[serde(default = "default_dead_zone")]
+    pub dead_zone: f32
+}
+
+fn default_dead_zone() -> f32 {
+    STICK_DEAD_ZONE
+}
+
+impl Default for InputBindings {
+    fn default() -> Self {
+        let bindings = HashMap::from([
+            (Action::MoveTo, vec![Binding::Mouse(MouseButton::Left)]),
+            (Action::AttackMove, vec![Binding::Mouse(MouseButton::Right)]),
+            (Action::Queue, vec![Binding::Key(KeyCode::LShift)]),
+            (Action::MoveUp, vec![Binding::Key(KeyCode::W), Binding::Gamepad(GamepadButtonType::DPadUp)]),
+            (Action::MoveDown, vec![Binding::Key(KeyCode::S), Binding::Gamepad(GamepadButtonType::DPadDown)]),
+            (Action::MoveLeft, vec![Binding::Key(KeyCode::A), Binding::Gamepad(GamepadButtonType::DPadLeft)]),
+            (Action::MoveRight, vec![Binding::Key(KeyCode::D), Binding::Gamepad(GamepadButtonType::DPadRight)]),
+            (Action::Jump, vec![Binding::Key(KeyCode::Space), Binding::Gamepad(GamepadButtonType::South)]),
+            (Action::Dash, vec![Binding::Key(KeyCode::E), Binding::Gamepad(GamepadButtonType::East)]),
+            (Action::RotateLeft, vec![Binding::Key(KeyCode::Q), Binding::Gamepad(GamepadButtonType::LeftTrigger)]),
+            (Action::RotateRight, vec![Binding::Key(KeyCode::R), Binding::Gamepad(GamepadButtonType::RightTrigger)]),
+            (Action::Pause, vec![Binding::Key(KeyCode::Escape), Binding::Gamepad(GamepadButtonType::Start)]),
+        ]);
+
+        InputBindings {
+            bindings,
+            dead_zone: STICK_DEAD_ZONE
+        }
+    }
+}
+
+impl InputBindings {
+    /// Give actions left out of a settings file their default bindings.
+    pub fn fill_defaults(&mut self) {
+        for (action, defaults) in InputBindings::default().bindings {
+            self.bindings.entry(action).or_insert(defaults);
+        }
+    }
+
+    pub fn get(
+        &self,
+        action: Action
+    ) -> &[Binding] {
+        self.bindings.get(&action).map_or(&[], |bindings| bindings.as_slice())
+    }
+
+    /// Replace every binding of `action` with `binding`.
+    pub fn rebind(
+        &mut self,
+        action: Action,
+        binding: Binding
+    ) {
+        self.bindings.insert(action, vec![binding]);
+    }
+}
+
+/// The actions held and pressed this frame, with the movement direction on screen.
+#[derive(Resource, Default)]
+pub struct ActionState {
+    pressed: Vec<Action>,
+    just_pressed: Vec<Action>,
+    /// Held movement direction, x to the right of the screen and y up it, at most 1 long.
+    pub movement: Vec2,
+    pub device: Device
+}
+
+impl ActionState {
+    pub fn pressed(
+        &self,
+        action: Action
+    ) -> bool {
+        self.pressed.contains(&action)
+    }
+
+    pub fn just_pressed(
+        &self,
+        action: Action
+    ) -> bool {
+        self.just_pressed.contains(&action)
+    }
+}
+
+/// Turns the raw keyboard, mouse and gamepad input into actions once per frame, ahead of
+/// everything reading them.
+pub struct ActionsPlugin;
+impl Plugin for ActionsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<InputBindings>()
+            .init_resource::<ActionState>()
+            .add_system_to_stage(CoreStage::PreUpdate, update_action_state.after(InputSystem));
+    }
+}
+
+fn binding_state(
+    binding: Binding,
+    keys: &Input<KeyCode>,
+    mouse: &Input<MouseButton>,
+    buttons: &Input<GamepadButton>,
+    gamepads: &Gamepads
+) -> (bool, bool) {
+    match binding {
+        Binding::Key(key) => (keys.pressed(key), keys.just_pressed(key)),
+        Binding::Mouse(button) => (mouse.pressed(button), mouse.just_pressed(button)),
+        Binding::Gamepad(button_type) => gamepads.iter().fold((false, false), |(pressed, just), gamepad| {
+            let button = GamepadButton::new(gamepad, button_type);
+            (pressed || buttons.pressed(button), just || buttons.just_pressed(button))
+        })
+    }
+}
+
+pub fn update_action_state(
+    bindings: Res<InputBindings>,
+    keys: Res<Input<KeyCode>>,
+    mouse: Res<Input<MouseButton>>,
+    buttons: Res<Input<GamepadButton>>,
+    axes: Res<Axis<GamepadAxis>>,
+    gamepads: Res<Gamepads>,
+    mut state: ResMut<ActionState>
+) {
+    state.pressed.clear();
+    state.just_pressed.clear();
+
+    for action in Action::ALL {
+        for binding in bindings.get(action) {
+            let (pressed, just_pressed) = binding_state(*binding, &keys, &mouse, &buttons, &gamepads);
+
+            if pressed && !state.pressed.contains(&action) {
+                state.pressed.push(action);
+            }
+            if just_pressed && !state.just_pressed.contains(&action) {
+                state.just_pressed.push(action);
+            }
+            if just_pressed && action.is_movement() {
+                state.device = match binding {
+                    Binding::Key(_) => Device::Keyboard,
+                    Binding::Mouse(_) => Device::Mouse,
+                    Binding::Gamepad(_) => Device::Gamepad
+                };
+            }
+        }
+    }
+
+    let held = |action| if state.pressed(action) { 1.0 } else { 0.0 };
+    let mut movement = Vec2::new(
+        held(Action::MoveRight) - held(Action::MoveLeft),
+        held(Action::MoveUp) - held(Action::MoveDown)
+    );
+
+    // The left stick of the first gamepad pushed past the dead zone.
+    for gamepad in gamepads.iter() {
+        let stick = Vec2::new(
+            axes.get(GamepadAxis::new(gamepad, GamepadAxisType::LeftStickX)).unwrap_or(0.0),
+            axes.get(GamepadAxis::new(gamepad, GamepadAxisType::LeftStickY)).unwrap_or(0.0)
+        );
+
+        if stick.length() > bindings.dead_zone {
+            movement = stick;
+            state.device = Device::Gamepad;
+            break;
+        }
+    }
+
+    state.movement = movement.clamp_length_max(1.0);
+}
@@ -0,0 +1,84 @@
+pub mod arena;
+#[cfg(feature = "windowed")]
+pub mod surface;
+#[cfg(feature = "windowed")]
+pub mod camera;
+pub mod collision;
+pub mod combat_log;
+pub mod flowfield;
+pub mod health;
+pub mod highscore;
+pub mod input;
+pub mod mob;
+pub mod obstacle;
+pub mod simulation;
+pub mod spatial;
+pub mod terrain;
+pub mod player;
+#[cfg(feature = "windowed")]
+pub mod player_input;
+pub mod progression;
+pub mod replay;
+pub mod rng;
+#[cfg(feature = "windowed")]
+pub mod settings;
+#[cfg(feature = "windowed")]
+pub mod ui;
+pub mod waves;
+pub mod weapon;
+
+use bevy::prelude::*;
+use iyes_loopless::prelude::*;
+
+/// Our Application State
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GameState {
+    MainMenu,
+    InGame,
+    Paused,
+    LevelUp,
+    Settings,
+    GameOver,
+}
+
+impl GameState {
+    pub const ALL: [GameState; 6] = [
+        GameState::MainMenu,
+        GameState::InGame,
+        GameState::Paused,
+        GameState::LevelUp,
+        GameState::Settings,
+        GameState::GameOver,
+    ];
+}
+
+/// The state the game was in before the current one, so the enter systems of `InGame` can tell a
+/// run starting apart from one resuming after a pause, a level up or the settings screen.
+#[derive(Resource)]
+pub struct PreviousState(pub GameState);
+
+/// Condition for enter systems of `InGame` that should only run when a run starts.
+pub fn run_starting(previous: Res<PreviousState>) -> bool {
+    matches!(previous.0, GameState::MainMenu | GameState::GameOver)
+}
+
+/// Runs on leaving every state, before the enter systems of the next one.
+fn record_previous_state(
+    current: Res<CurrentState<GameState>>,
+    mut previous: ResMut<PreviousState>
+) {
+    previous.0 = current.0;
+}
+
+/// Adds the `GameState`, starting in the main menu, and keeps the `PreviousState` up to date.
+pub struct GameStatePlugin;
+impl Plugin for GameStatePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_loopless_state(GameState::MainMenu)
+            .insert_resource(PreviousState(GameState::MainMenu));
+
+        for state in GameState::ALL {
+            app.add_exit_system(state, record_previous_state);
+        }
+    }
+}
@@ -1,46 +1,71 @@
-mod surface;
-mod mob;
-mod simulation;
-mod player;
-mod ui;
-
 use bevy::{
     prelude::*,
 };
-use iyes_loopless::prelude::*;
 use bevy::diagnostic::{FrameTimeDiagnosticsPlugin, LogDiagnosticsPlugin};
-use bevy::window::PresentMode;
-
-use crate::player::PlayerPlugin;
-use crate::simulation::SimulationPlugin;
-use crate::surface::SurfacePlugin;
-use crate::ui::UIPlugin;
 
-/// Our Application State
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
-enum GameState {
-    MainMenu,
-    InGame,
-    GameOver,
-}
+use amplitude::GameStatePlugin;
+use amplitude::combat_log::CombatLog;
+use amplitude::camera::CameraPlugin;
+use amplitude::input::ActionsPlugin;
+use amplitude::player::PlayerPlugin;
+use amplitude::player_input::PlayerInputPlugin;
+use amplitude::progression::ProgressionPlugin;
+use amplitude::rng::{seed_from_args, SimulationRng};
+use amplitude::replay::{replay_from_args, ReplayPlayback, ReplayPlugin};
+use amplitude::settings::{Settings, SettingsPlugin};
+use amplitude::simulation::{SimulationPlugin, TickRate};
+use amplitude::surface::SurfacePlugin;
+use amplitude::ui::UIPlugin;
 
 fn main() {
-    App::new()
-        .insert_resource(Msaa { samples: 1 })
+    let mut app = App::new();
+
+    // Watching a replay means running with its seed and tick rate instead of our own.
+    match replay_from_args() {
+        Some(replay) => {
+            app.insert_resource(SimulationRng::new(replay.seed))
+                .insert_resource(TickRate(replay.tick_rate))
+                .insert_resource(ReplayPlayback::new(replay));
+        }
+        None => {
+            app.insert_resource(SimulationRng::new(seed_from_args()));
+        }
+    }
+
+    if let Some(log) = CombatLog::from_args() {
+        app.insert_resource(log);
+    }
+
+    // The window is created with the saved settings, the `SettingsPlugin` applies later changes.
+    let settings = Settings::load();
+
+    app.insert_resource(Msaa { samples: settings.msaa })
+        .insert_resource(settings.bindings.clone())
         .add_plugins(DefaultPlugins.set(WindowPlugin {
             window: WindowDescriptor {
                 title: "Amplitude".to_string(),
-                present_mode: PresentMode::AutoVsync,
+                present_mode: settings.present_mode(),
                 ..default()
             },
             ..default()
+        }).set(AssetPlugin {
+            // Hot reload wave tables and other data files while the game is running
+            watch_for_changes: true,
+            ..default()
         }))
-        .add_loopless_state(GameState::MainMenu)
+        .insert_resource(settings)
+        .add_plugin(GameStatePlugin)
         .add_plugin(LogDiagnosticsPlugin::default())
         .add_plugin(FrameTimeDiagnosticsPlugin::default())
         .add_plugin(SurfacePlugin)
         .add_plugin(SimulationPlugin)
         .add_plugin(PlayerPlugin)
+        .add_plugin(ActionsPlugin)
+        .add_plugin(SettingsPlugin)
+        .add_plugin(CameraPlugin)
+        .add_plugin(PlayerInputPlugin)
+        .add_plugin(ProgressionPlugin)
+        .add_plugin(ReplayPlugin)
         .add_plugin(UIPlugin)
         .run();
 }
\ No newline at end of file
@@ -1,65 +1,295 @@
+use std::collections::HashMap;
 use std::time::Duration;
 use bevy::{
     prelude::*,
 };
-use bevy::time::Stopwatch;
+use crate::health::Health;
+use crate::simulation::DEFAULT_TICK_RATE;
+use crate::terrain::Terrain;
 
+/// Drag per tick at the `DEFAULT_TICK_RATE`, scaled by the step for other rates.
 pub const DRAG_CONSTANT: f32 = 0.03;
+pub const MOB_SIZE: f32 = 1.0;
+
+/// Largest `size` of any archetype, used to bound collision lookups.
+pub const MAX_MOB_SIZE: f32 = 2.0;
 
 pub const ATTACKED_COLOR: Color = Color::rgb(0.9, 0.4, 0.4);
 pub const ATTACKED_FLASH_TIME: f32 = 0.5;
 
+pub const DEFAULT_ARCHETYPE: &str = "grunt";
+
 #[derive(Component)]
 pub struct Mob {
     pub(crate) pos: Vec3,
     pub(crate) vel: Vec3,
     pub(crate) acc: Vec3,
     pub(crate) force: Vec3,
-    pub(crate) health: f32,
     pub(crate) strength: f32,
     pub(crate) mass: f32,
+    pub(crate) speed: f32,
+    pub(crate) size: f32,
+    pub(crate) contact_damage: f32,
 
-    pub color: Color,
-    pub last_damaged: Stopwatch
+    pub archetype: String,
+    pub behaviour: MobBehaviour,
+    pub attack: Timer,
+    pub color: Color
 }
 
 impl Mob {
-    pub fn update(&mut self, dt: f32) {
-        // Apply force to mob
-        self.acc = self.mass * self.force;
+    /// A mob of the given archetype, with contact damage scaled by `strength`.
+    pub fn new(
+        id: &str,
+        archetype: &MobArchetype,
+        position: Vec3,
+        strength: f32
+    ) -> Self {
+        let attack = match archetype.behaviour {
+            MobBehaviour::Ranged { fire_rate, .. } => fire_rate,
+            _ => 1.0
+        };
+
+        Mob {
+            pos: position,
+            vel: Vec3::default(),
+            acc: Vec3::default(),
+            force: Vec3::default(),
+            strength,
+            mass: archetype.mass,
+            speed: archetype.speed,
+            size: archetype.size,
+            contact_damage: archetype.contact_damage * strength,
+            archetype: id.to_string(),
+            behaviour: archetype.behaviour.clone(),
+            attack: Timer::new(Duration::from_secs_f32(attack), TimerMode::Repeating),
+            color: archetype.color
+        }
+    }
+
+    /// Health of a mob of the given archetype, scaled by `strength`.
+    pub fn health(
+        archetype: &MobArchetype,
+        strength: f32
+    ) -> Health {
+        Health::new(archetype.health * strength)
+    }
+
+    pub fn update(&mut self, dt: f32, terrain: &Terrain) {
+        // Apply force to mob, heavier mobs are slower to get going. Mobs only move across the ground
+        self.acc = self.force / self.mass;
+        self.acc.y = 0.;
         self.vel += self.acc * dt;
-        self.vel -= DRAG_CONSTANT * self.vel * self.vel.length();
+        self.vel -= DRAG_CONSTANT * self.vel * self.vel.length() * dt * DEFAULT_TICK_RATE as f32;
         self.pos += self.vel * dt;
+        self.pos.y = terrain.height(self.pos.x, self.pos.z) + self.size * 0.5;
 
         // Reset force
         self.force = Vec3::default();
+    }
+}
+
+/// How a mob moves and attacks, on top of the flocking forces every mob feels.
+#[derive(Clone)]
+pub enum MobBehaviour {
+    /// Walks straight at the player.
+    Chase,
+    /// Holds `range` away from the player and fires a bolt every `fire_rate` seconds.
+    Ranged { range: f32, fire_rate: f32, bolt_damage: f32 },
+    /// Chases the player and breaks into `count` mobs of archetype `into` when killed.
+    Splitter { into: String, count: usize },
+}
+
+#[derive(Clone, Copy)]
+pub enum MobShape {
+    Cube,
+    Sphere,
+    Capsule,
+}
+
+/// Stats and looks shared by every mob of one kind.
+#[derive(Clone)]
+pub struct MobArchetype {
+    pub health: f32,
+    pub mass: f32,
+    /// Multiplier on the pull towards the player.
+    pub speed: f32,
+    /// Damage per second drained from the player while touching, which armor and
+    /// invulnerability don't stop.
+    pub contact_damage: f32,
+    pub size: f32,
+    pub shape: MobShape,
+    pub color: Color,
+    pub behaviour: MobBehaviour
+}
+
+impl MobArchetype {
+    pub fn mesh(&self) -> Mesh {
+        match self.shape {
+            MobShape::Cube => Mesh::from(shape::Cube { size: self.size }),
+            MobShape::Sphere => Mesh::from(shape::Icosphere { radius: self.size * 0.5, subdivisions: 2 }),
+            MobShape::Capsule => Mesh::from(shape::Capsule {
+                radius: self.size * 0.3,
+                depth: self.size * 0.4,
+                ..default()
+            }),
+        }
+    }
+}
+
+/// Every mob archetype that can be spawned, keyed by the ID used in wave files.
+#[derive(Resource)]
+pub struct MobArchetypes {
+    archetypes: HashMap<String, MobArchetype>
+}
 
-        // Tick last damaged timer
-        self.last_damaged.tick(Duration::from_secs_f32(dt));
+impl MobArchetypes {
+    pub fn get(
+        &self,
+        id: &str
+    ) -> Option<&MobArchetype> {
+        self.archetypes.get(id)
     }
 
-    pub fn damage(
+    pub fn insert(
         &mut self,
-        damage: f32,
+        id: &str,
+        archetype: MobArchetype
     ) {
-        // Damage the mob
-        self.health -= damage;
+        self.archetypes.insert(id.to_string(), archetype);
+    }
+}
+
+impl Default for MobArchetypes {
+    fn default() -> Self {
+        let mut registry = MobArchetypes {
+            archetypes: HashMap::new()
+        };
 
-        // Reset last damaged timer
-        self.last_damaged.reset();
+        registry.insert("grunt", MobArchetype {
+            health: 10.0,
+            mass: 1.0,
+            speed: 1.0,
+            contact_damage: 1.0,
+            size: MOB_SIZE,
+            shape: MobShape::Cube,
+            color: Color::rgb(1.0, 0.9, 0.9),
+            behaviour: MobBehaviour::Chase
+        });
+        registry.insert("tank", MobArchetype {
+            health: 40.0,
+            mass: 4.0,
+            speed: 0.5,
+            contact_damage: 3.0,
+            size: 1.6,
+            shape: MobShape::Cube,
+            color: Color::rgb(1.0, 0.6, 0.6),
+            behaviour: MobBehaviour::Chase
+        });
+        registry.insert("runner", MobArchetype {
+            health: 5.0,
+            mass: 0.5,
+            speed: 2.5,
+            contact_damage: 0.5,
+            size: 0.7,
+            shape: MobShape::Sphere,
+            color: Color::rgb(1.0, 1.0, 0.7),
+            behaviour: MobBehaviour::Chase
+        });
+        registry.insert("ranged", MobArchetype {
+            health: 8.0,
+            mass: 1.0,
+            speed: 1.0,
+            contact_damage: 0.5,
+            size: MOB_SIZE,
+            shape: MobShape::Capsule,
+            color: Color::rgb(0.7, 0.8, 1.0),
+            behaviour: MobBehaviour::Ranged { range: 8.0, fire_rate: 2.0, bolt_damage: 5.0 }
+        });
+        registry.insert("splitter", MobArchetype {
+            health: 20.0,
+            mass: 2.0,
+            speed: 0.8,
+            contact_damage: 1.0,
+            size: 1.3,
+            shape: MobShape::Sphere,
+            color: Color::rgb(0.8, 1.0, 0.8),
+            behaviour: MobBehaviour::Splitter { into: "runner".to_string(), count: 3 }
+        });
+
+        registry
+    }
+}
+
+/// Projectile fired at the player by ranged mobs.
+#[derive(Component)]
+pub struct MobBolt {
+    /// The mob that fired it.
+    pub source: Entity,
+    pub pos: Vec3,
+    pub vel: Vec3,
+    pub damage: f32,
+    pub lifetime: Timer
+}
+
+impl MobBolt {
+    pub fn update(
+        &mut self,
+        dt: f32,
+        terrain: &Terrain
+    ) {
+        self.pos += self.vel * dt;
+        self.pos.y = self.pos.y.max(terrain.height(self.pos.x, self.pos.z) + 0.15);
     }
 }
 
-pub fn get_mob_type(
-    strength: f32
-) -> Color {
-    let strength_type = strength as i32;
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn heavier_mobs_are_slower_to_get_going() {
+        let archetypes = MobArchetypes::default();
+        let terrain = Terrain::new(0);
+        let mut light = Mob::new("runner", archetypes.get("runner").unwrap(), Vec3::ZERO, 1.0);
+        let mut heavy = Mob::new("tank", archetypes.get("tank").unwrap(), Vec3::ZERO, 1.0);
 
-    if strength_type < 1 {
-        return Color::rgb(1.0, 1.0, 1.0);
-    } else if strength_type < 2 {
-        return Color::rgb(1.0, 0.9, 0.9);
+        light.force = Vec3::X;
+        heavy.force = Vec3::X;
+        light.update(0.1, &terrain);
+        heavy.update(0.1, &terrain);
+
+        assert!(light.vel.x > heavy.vel.x);
+        assert!((heavy.acc.x - 1.0 / heavy.mass).abs() < 1e-5);
     }
 
-    return Color::rgb(1.0, 0.6, 0.6);
-}
\ No newline at end of file
+    #[test]
+    fn drag_slows_mobs_alike_at_any_tick_rate() {
+        let archetypes = MobArchetypes::default();
+        let terrain = Terrain::new(0);
+
+        // Coast for a second from the same speed, with no force pushing the mob along.
+        let speed_after_a_second = |tick_rate: usize| {
+            let mut mob = Mob::new(DEFAULT_ARCHETYPE, archetypes.get(DEFAULT_ARCHETYPE).unwrap(), Vec3::ZERO, 1.0);
+            mob.vel = Vec3::X * 10.0;
+            for _ in 0..tick_rate {
+                mob.update(1.0 / tick_rate as f32, &terrain);
+            }
+            mob.vel.x
+        };
+
+        let default_rate = speed_after_a_second(DEFAULT_TICK_RATE as usize);
+        let doubled_rate = speed_after_a_second(DEFAULT_TICK_RATE as usize * 2);
+        assert!((default_rate - doubled_rate).abs() < default_rate * 0.05);
+    }
+
+    #[test]
+    fn contact_damage_scales_with_strength() {
+        let archetypes = MobArchetypes::default();
+        let grunt = archetypes.get(DEFAULT_ARCHETYPE).unwrap();
+
+        let mob = Mob::new(DEFAULT_ARCHETYPE, grunt, Vec3::ZERO, 2.0);
+        assert_eq!(mob.contact_damage, grunt.contact_damage * 2.0);
+        assert_eq!(Mob::health(grunt, 2.0).max, grunt.health * 2.0);
+    }
+}
@@ -0,0 +1,373 @@
+use bevy::{
+    prelude::*,
+};
+use noise::{NoiseFn, Perlin};
+use crate::spatial::SpatialGrid;
+use crate::terrain::{GROUND_HALF_EXTENT, Terrain};
+
+/// Height of obstacles above the ground.
+pub const OBSTACLE_HEIGHT: f32 = 3.0;
+pub const OBSTACLE_COLOR: Color = Color::rgb(0.22, 0.2, 0.24);
+
+/// Spacing of the grid obstacles are placed on, at most one per cell.
+pub const OBSTACLE_SPACING: f32 = 8.0;
+pub const OBSTACLE_FREQUENCY: f64 = 0.13;
+/// Noise above this places a pillar, below its negative a wall.
+pub const OBSTACLE_THRESHOLD: f32 = 0.25;
+/// Radius around the player spawn that is kept clear.
+pub const OBSTACLE_CLEAR_RADIUS: f32 = 10.0;
+
+/// Distance from an obstacle at which mobs start steering around it.
+pub const OBSTACLE_AVOIDANCE_DISTANCE: f32 = 3.0;
+pub const OBSTACLE_AVOIDANCE_FORCE: f32 = 10.0;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ObstacleShape {
+    /// Round pillar.
+    Pillar { radius: f32 },
+    /// Axis aligned wall, `half_extents` along x and z.
+    Wall { half_extents: Vec2 },
+}
+
+/// Static obstacle on the ground that blocks the player, mobs and projectiles.
+#[derive(Component, Clone, Copy, Debug)]
+pub struct Obstacle {
+    pub pos: Vec3,
+    pub shape: ObstacleShape
+}
+
+impl Obstacle {
+    /// Signed distance on the ground plane from the edge of the obstacle to `position`, negative
+    /// inside, along with the direction pointing out of the obstacle.
+    pub fn distance(
+        &self,
+        position: Vec3
+    ) -> (f32, Vec3) {
+        let mut offset = position - self.pos;
+        offset.y = 0.;
+
+        match self.shape {
+            ObstacleShape::Pillar { radius } => {
+                let length = offset.length();
+                let normal = if length > f32::EPSILON { offset / length } else { Vec3::X };
+
+                (length - radius, normal)
+            }
+            ObstacleShape::Wall { half_extents } => {
+                let qx = offset.x.abs() - half_extents.x;
+                let qz = offset.z.abs() - half_extents.y;
+
+                if qx > 0. || qz > 0. {
+                    let outside = Vec3::new(qx.max(0.) * offset.x.signum(), 0., qz.max(0.) * offset.z.signum());
+                    (outside.length(), outside.normalize_or_zero())
+                } else if qx > qz {
+                    (qx, Vec3::X * offset.x.signum())
+                } else {
+                    (qz, Vec3::Z * offset.z.signum())
+                }
+            }
+        }
+    }
+
+    /// Furthest the obstacle reaches from its centre on the ground plane.
+    pub fn extent(&self) -> f32 {
+        match self.shape {
+            ObstacleShape::Pillar { radius } => radius,
+            ObstacleShape::Wall { half_extents } => half_extents.length()
+        }
+    }
+
+    /// Whether a circle of `radius` at `position` overlaps the obstacle.
+    pub fn overlaps(
+        &self,
+        position: Vec3,
+        radius: f32
+    ) -> bool {
+        self.distance(position).0 < radius
+    }
+
+    pub fn mesh(
+        &self,
+        depth: f32
+    ) -> Mesh {
+        match self.shape {
+            ObstacleShape::Pillar { radius } => Mesh::from(shape::Capsule {
+                radius,
+                depth: OBSTACLE_HEIGHT + depth,
+                ..default()
+            }),
+            ObstacleShape::Wall { half_extents } => Mesh::from(shape::Box {
+                min_x: -half_extents.x,
+                max_x: half_extents.x,
+                min_y: -depth,
+                max_y: OBSTACLE_HEIGHT,
+                min_z: -half_extents.y,
+                max_z: half_extents.y
+            }),
+        }
+    }
+}
+
+/// Obstacles placed at the start of a run.
+///
+/// Generated from the run seed by default, insert one before adding the `SimulationPlugin` to
+/// use a fixed layout instead.
+#[derive(Resource, Clone, Default)]
+pub struct ObstacleLayout {
+    pub obstacles: Vec<Obstacle>
+}
+
+impl ObstacleLayout {
+    /// Scatter pillars and walls over the arena from a noise field, keeping the spawn clear.
+    pub fn generate(
+        seed: u32,
+        terrain: &Terrain
+    ) -> Self {
+        // Offset from the terrain seed so obstacles don't just follow the hills.
+        let noise = Perlin::new(seed.wrapping_add(1));
+        let cells = (GROUND_HALF_EXTENT / OBSTACLE_SPACING) as i32;
+        let mut obstacles = Vec::new();
+
+        for x in -cells..cells {
+            for z in -cells..cells {
+                let centre = Vec3::new(x as f32 + 0.5, 0., z as f32 + 0.5) * OBSTACLE_SPACING;
+                let sample = |dx: f64, dz: f64| -> f32 {
+                    noise.get([
+                        centre.x as f64 * OBSTACLE_FREQUENCY + dx,
+                        centre.z as f64 * OBSTACLE_FREQUENCY + dz
+                    ]) as f32
+                };
+
+                let value = sample(0., 0.);
+                if value.abs() < OBSTACLE_THRESHOLD {
+                    continue;
+                }
+
+                // Jitter within the cell so the layout doesn't look like a grid.
+                let mut pos = centre + Vec3::new(sample(17.3, 0.), 0., sample(0., 17.3)) * OBSTACLE_SPACING * 0.3;
+                if pos.length() < OBSTACLE_CLEAR_RADIUS {
+                    continue;
+                }
+                pos.y = terrain.height(pos.x, pos.z);
+
+                let shape = if value > 0. {
+                    ObstacleShape::Pillar { radius: 0.6 + value * 1.5 }
+                } else {
+                    let length = OBSTACLE_SPACING * (0.25 - value * 0.5);
+
+                    if sample(31.7, 31.7) > 0. {
+                        ObstacleShape::Wall { half_extents: Vec2::new(length, 0.5) }
+                    } else {
+                        ObstacleShape::Wall { half_extents: Vec2::new(0.5, length) }
+                    }
+                };
+
+                obstacles.push(Obstacle { pos, shape });
+            }
+        }
+
+        ObstacleLayout { obstacles }
+    }
+}
+
+/// Obstacles indexed by the cell their centre is in, so only the ones near a position have to be
+/// tested against it.
+#[derive(Resource)]
+pub struct ObstacleGrid {
+    grid: SpatialGrid<Obstacle>,
+    /// Largest `Obstacle::extent`, how far past its cell an obstacle can reach.
+    extent: f32
+}
+
+impl Default for ObstacleGrid {
+    fn default() -> Self {
+        // At most one obstacle is placed per spacing, so a cell holds about one.
+        ObstacleGrid {
+            grid: SpatialGrid::new(OBSTACLE_SPACING),
+            extent: 0.0
+        }
+    }
+}
+
+impl ObstacleGrid {
+    pub fn clear(&mut self) {
+        self.grid.clear();
+        self.extent = 0.0;
+    }
+
+    pub fn insert(
+        &mut self,
+        obstacle: Obstacle
+    ) {
+        self.extent = self.extent.max(obstacle.extent());
+        self.grid.insert_at(obstacle.pos, obstacle);
+    }
+
+    /// Every obstacle that could be within `radius` of `position`. Callers still need to check
+    /// the actual distance.
+    pub fn near(
+        &self,
+        position: Vec3,
+        radius: f32
+    ) -> impl Iterator<Item = &Obstacle> + '_ {
+        self.grid.neighbours(position, radius + self.extent)
+    }
+}
+
+/// Re-index the obstacles when they are spawned or change.
+pub fn update_obstacle_grid(
+    mut grid: ResMut<ObstacleGrid>,
+    obstacles: Query<&Obstacle>,
+    changed: Query<(), Changed<Obstacle>>
+) {
+    if changed.is_empty() {
+        return;
+    }
+
+    grid.clear();
+    for obstacle in obstacles.iter() {
+        grid.insert(*obstacle);
+    }
+}
+
+/// Spawn an entity for every obstacle in the layout, sunk into the ground far enough to cover
+/// the hills under it.
+pub fn spawn_obstacles(
+    commands: &mut Commands,
+    meshes: &mut ResMut<Assets<Mesh>>,
+    materials: &mut ResMut<Assets<StandardMaterial>>,
+    layout: &ObstacleLayout,
+    terrain: &Terrain
+) {
+    let material = materials.add(StandardMaterial {
+        base_color: OBSTACLE_COLOR,
+        perceptual_roughness: 0.8,
+        ..default()
+    });
+
+    for obstacle in layout.obstacles.iter() {
+        let mut transform = Transform::from_translation(obstacle.pos);
+        if let ObstacleShape::Pillar { .. } = obstacle.shape {
+            // Capsules are centred, walls start from their base.
+            transform.translation.y += (OBSTACLE_HEIGHT - terrain.amplitude) * 0.5;
+        }
+
+        commands.spawn(PbrBundle {
+            mesh: meshes.add(obstacle.mesh(terrain.amplitude)),
+            material: material.clone(),
+            transform,
+            ..default()
+        }).insert(*obstacle);
+    }
+}
+
+/// Steering that takes a mob around the obstacles near it: a push away from each close
+/// obstacle, and the part of `desired` that heads into it turned along its edge instead.
+pub fn obstacle_avoidance(
+    obstacles: &ObstacleGrid,
+    position: Vec3,
+    radius: f32,
+    desired: Vec3
+) -> Vec3 {
+    let mut force = Vec3::default();
+
+    for obstacle in obstacles.near(position, radius + OBSTACLE_AVOIDANCE_DISTANCE) {
+        let (distance, normal) = obstacle.distance(position);
+        let gap = distance - radius;
+        if gap >= OBSTACLE_AVOIDANCE_DISTANCE {
+            continue;
+        }
+
+        let weight = (1.0 - gap / OBSTACLE_AVOIDANCE_DISTANCE).min(1.0);
+        force += normal * OBSTACLE_AVOIDANCE_FORCE * weight;
+
+        let inward = desired.dot(normal);
+        if inward < 0. {
+            // Heading straight at it, pick a side.
+            let tangent = (desired - normal * inward)
+                .try_normalize()
+                .unwrap_or(Vec3::new(-normal.z, 0., normal.x));
+            force += (tangent + normal) * -inward * weight;
+        }
+    }
+
+    force
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pillar(x: f32, z: f32, radius: f32) -> Obstacle {
+        Obstacle { pos: Vec3::new(x, 0., z), shape: ObstacleShape::Pillar { radius } }
+    }
+
+    fn wall(x: f32, z: f32, half_extents: Vec2) -> Obstacle {
+        Obstacle { pos: Vec3::new(x, 0., z), shape: ObstacleShape::Wall { half_extents } }
+    }
+
+    #[test]
+    fn pillar_distance_is_measured_from_the_edge() {
+        let obstacle = pillar(0., 0., 1.0);
+
+        let (distance, normal) = obstacle.distance(Vec3::new(3.0, 5.0, 0.));
+        assert!((distance - 2.0).abs() < 1e-5);
+        assert_eq!(normal, Vec3::X);
+
+        let (distance, _) = obstacle.distance(Vec3::new(0., 0., 0.5));
+        assert!((distance + 0.5).abs() < 1e-5);
+    }
+
+    #[test]
+    fn wall_distance_points_out_of_the_nearest_face() {
+        let obstacle = wall(0., 0., Vec2::new(2.0, 0.5));
+
+        // Beside a long face.
+        let (distance, normal) = obstacle.distance(Vec3::new(1.0, 0., -1.5));
+        assert!((distance - 1.0).abs() < 1e-5);
+        assert_eq!(normal, Vec3::NEG_Z);
+
+        // Past a corner.
+        let (distance, normal) = obstacle.distance(Vec3::new(5.0, 0., 4.5));
+        assert!((distance - 5.0).abs() < 1e-5);
+        assert!((normal - Vec3::new(0.6, 0., 0.8)).length() < 1e-5);
+
+        // Inside, closer to the end than the sides.
+        let (distance, normal) = obstacle.distance(Vec3::new(1.9, 0., 0.));
+        assert!((distance + 0.1).abs() < 1e-5);
+        assert_eq!(normal, Vec3::X);
+    }
+
+    #[test]
+    fn the_grid_finds_obstacles_reaching_into_range() {
+        let mut grid = ObstacleGrid::default();
+        grid.insert(pillar(0., 0., 1.0));
+        // Centred three cells away, but long enough to reach back towards the origin.
+        grid.insert(wall(OBSTACLE_SPACING * 3.0, 0., Vec2::new(OBSTACLE_SPACING * 2.5, 0.5)));
+        grid.insert(pillar(OBSTACLE_SPACING * 10.0, 0., 1.0));
+
+        let near: Vec<Vec3> = grid.near(Vec3::new(OBSTACLE_SPACING * 0.5, 0., 0.), 1.0)
+            .map(|obstacle| obstacle.pos)
+            .collect();
+        assert_eq!(near.len(), 2);
+        assert!(!near.contains(&Vec3::new(OBSTACLE_SPACING * 10.0, 0., 0.)));
+
+        grid.clear();
+        assert_eq!(grid.near(Vec3::ZERO, 1.0).count(), 0);
+    }
+
+    #[test]
+    fn avoidance_steers_around_obstacles_ahead() {
+        let mut grid = ObstacleGrid::default();
+        grid.insert(pillar(0., 0., 1.0));
+
+        // Heading straight for the pillar, with a little sideways drift to pick a side.
+        let force = obstacle_avoidance(&grid, Vec3::new(-2.0, 0., 0.), 0.5, Vec3::new(1.0, 0., 0.1));
+        assert!(force.x < 0.);
+        assert!(force.z > 0.);
+
+        // Nothing close enough to care about.
+        let force = obstacle_avoidance(&grid, Vec3::new(-10.0, 0., 0.), 0.5, Vec3::X);
+        assert_eq!(force, Vec3::ZERO);
+    }
+}
@@ -3,202 +3,471 @@ use bevy::{
     prelude::*,
 };
 use bevy::time::Stopwatch;
-use bevy_mod_raycast::RaycastSource;
 use iyes_loopless::prelude::*;
-use crate::GameState;
-use crate::surface::{CAMERA_DISTANCE, GameCamera, Surface};
-use crate::ui::despawn_with;
+use serde::{Deserialize, Serialize};
+use crate::{GameState, run_starting};
+use crate::health::Health;
+use crate::mob::Mob;
+use crate::simulation::{SIMULATION_TIMESTEP, SimulationLabel, STEP_INTEGRATE, STEP_PREPARE};
+use crate::simulation::PLAYER_SIZE;
+use crate::terrain::Terrain;
+use crate::weapon::{Blade, fire_weapons, Targeting, update_weapon_effects, Weapon, WeaponEffect, WeaponKind};
 
-pub const GRAVITY: f32 = -1.;
+pub const GRAVITY: f32 = -40.;
+/// Share of the pull towards the target the player still has in the air.
 pub const SPEED: f32 = 0.3;
+/// How much of that air control works sideways to the direction of travel, compared to along it.
 pub const SIDE_SPEED_FACTOR: f32 = 1.;
-pub const JUMP_ACCEL: f32 = -GRAVITY * 40.;
+/// Upwards acceleration for the first `JUMP_TIMER` seconds of a jump, on top of gravity.
+pub const JUMP_ACCEL: f32 = -GRAVITY * 2.5;
 pub const JUMP_TIMER: f32 = 0.2;
+/// Seconds after a jump or dash before the next one.
+pub const JUMP_COOLDOWN: f32 = 0.8;
+pub const DASH_COOLDOWN: f32 = 1.5;
+/// Speed added in the direction of the target by a dash.
+pub const DASH_SPEED: f32 = 20.0;
+
+/// Distance from a waypoint at which the player moves on to the next one.
+pub const WAYPOINT_RADIUS: f32 = 1.0;
+/// How far from the cursor a mob can be and still be picked by an attack-move.
+pub const ATTACK_PICK_RADIUS: f32 = 3.0;
+/// Distance the player closes to on the mob they are attack-moving on.
+pub const ATTACK_RANGE: f32 = 5.0;
+/// How far the cursor has to move while the button is held before the target follows it.
+pub const FOLLOW_MIN_DISTANCE: f32 = 0.5;
+
+/// Top speed when steered with the keyboard or a gamepad.
+pub const DIRECT_SPEED: f32 = 8.0;
+/// How quickly the player reaches the held direction's speed, per second.
+pub const DIRECT_RESPONSE: f32 = 6.0;
+
+pub const PLAYER_MASS: f32 = 5.0;
+
+pub const PLAYER_HEALTH: f32 = 100.0;
+/// Health regained per second.
+pub const PLAYER_REGEN: f32 = 1.0;
+pub const PLAYER_ARMOR: f32 = 0.0;
+/// Seconds after being hit during which the player can't be hit again.
+pub const PLAYER_INVULNERABILITY: f32 = 0.5;
 
 pub const PLAYER_COLOUR: Color = Color::rgb(0.9, 0.9, 0.9);
 pub const TARGET_COLOUR: Color = Color::rgba(0.9, 0.9, 0.9, 0.3);
 
-pub const PROJECTILE_SPAWN_RATE: f32 = 0.3;
-pub const PROJECTILE_SPEED: f32 = 5.0;
-pub const PROJECTILE_LIFETIME: f32 = 10.0;
+/// Weapon the player starts every run with.
+pub const STARTING_WEAPON: WeaponKind = WeaponKind::Seeker;
 
 pub struct PlayerPlugin;
 impl Plugin for PlayerPlugin {
     fn build(&self, app: &mut App) {
-        app.add_enter_system(GameState::InGame, spawn_player)
-            .add_exit_system(GameState::MainMenu, despawn_with::<Player>)
-            .add_system_set(
-            ConditionSet::new()
-                .run_in_state(GameState::InGame)
-                .with_system(player_control)
-                .with_system(handle_mouse_clicks)
-                .with_system(projectile_spawner)
-                .into()
+        // Physics runs in the simulation timestep created by the `SimulationPlugin`,
+        // so that plugin has to be added first.
+        app.add_enter_system(GameState::InGame, spawn_player.run_if(run_starting))
+            .add_system(update_target_markers.run_in_state(GameState::InGame))
+            .add_exit_system(GameState::GameOver, despawn_run)
+            .add_enter_system(GameState::MainMenu, despawn_run)
+            .add_fixed_timestep_system_set(
+                SIMULATION_TIMESTEP,
+                STEP_PREPARE,
+                ConditionSet::new()
+                    .run_in_state(GameState::InGame)
+                    .label(PlayerLabel::Control)
+                    .with_system(player_control)
+                    .into()
+            ).add_fixed_timestep_system_set(
+                SIMULATION_TIMESTEP,
+                STEP_INTEGRATE,
+                ConditionSet::new()
+                    .run_in_state(GameState::InGame)
+                    .label(PlayerLabel::Weapons)
+                    .after(SimulationLabel::Mobs)
+                    .before(SimulationLabel::Projectiles)
+                    .with_system(fire_weapons)
+                    .with_system(update_weapon_effects)
+                    .into()
             );
     }
 }
 
+#[derive(SystemLabel)]
+pub enum PlayerLabel {
+    Control,
+    Weapons,
+    Upgrades,
+}
+
+/// What clicking on the ground asks of the player.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum MoveOrder {
+    /// Walk to the point, dropping any queued waypoints.
+    #[default]
+    Move,
+    /// Walk to the point after the ones already queued.
+    Queue,
+    /// Walk up to the mob nearest the point and focus the weapons on it.
+    Attack,
+}
+
+/// Moves the player can make on top of walking to the target.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Ability {
+    /// Hop into the air, high enough to clear the mobs.
+    Jump,
+    /// Burst of speed towards the target.
+    Dash,
+}
+
+impl Ability {
+    pub fn cooldown(&self) -> f32 {
+        match self {
+            Ability::Jump => JUMP_COOLDOWN,
+            Ability::Dash => DASH_COOLDOWN
+        }
+    }
+}
+
 #[derive(Component, Default)]
 pub struct Player {
-    pos: Vec3,
-    vel: Vec3,
+    pub(crate) pos: Vec3,
+    pub(crate) vel: Vec3,
     acc: Vec3,
-    pub health: f32,
+    /// Multiplier on how hard the player accelerates towards the target.
+    pub speed: f32,
     pub target_position: Vec3,
-    pub last_jump: Stopwatch
+    /// Horizontal direction held on the keyboard or a gamepad, moved in directly instead of
+    /// walking to `target_position`. `None` while the mouse is in control.
+    pub move_direction: Option<Vec3>,
+    /// Points walked to in order once `target_position` is reached.
+    pub waypoints: Vec<Vec3>,
+    /// Where an attack-move was ordered, picked a mob for on the next tick.
+    attack_point: Option<Vec3>,
+    /// Mob being attack-moved on, kept in range and focused by the weapons until it dies.
+    pub attack_target: Option<Entity>,
+    /// Ability to use on the next tick, set by the input handlers.
+    pub ability: Option<Ability>,
+    /// Time since the last jump or dash, which share a cooldown.
+    pub last_jump: Stopwatch,
+    airborne: bool
 }
 
 impl Player {
+    pub fn is_airborne(&self) -> bool {
+        self.airborne
+    }
+
+    /// Whether the bottom of the player is above `height`, so they pass over anything lower.
+    pub fn clears(&self, height: f32) -> bool {
+        self.pos.y - PLAYER_SIZE * 0.5 > height
+    }
+
+    /// Follow a click on the ground at `position`, taking over from the keyboard or gamepad.
+    pub fn order(
+        &mut self,
+        order: MoveOrder,
+        position: Vec3
+    ) {
+        self.move_direction = None;
+
+        match order {
+            MoveOrder::Move => {
+                self.target_position = position;
+                self.waypoints.clear();
+                self.attack_point = None;
+                self.attack_target = None;
+            }
+            MoveOrder::Queue => {
+                self.waypoints.push(position);
+            }
+            MoveOrder::Attack => {
+                self.waypoints.clear();
+                self.attack_point = Some(position);
+                self.attack_target = None;
+            }
+        }
+    }
+
+    /// Pick the mob an attack-move was ordered on, walk into range of it and on through the
+    /// waypoints once there is nothing to attack.
+    fn follow_orders(
+        &mut self,
+        mobs: &Query<(Entity, &Mob, &Health)>
+    ) {
+        if let Some(point) = self.attack_point.take() {
+            let nearest = mobs.iter()
+                .filter(|(_, _, health)| !health.is_dead())
+                .map(|(entity, mob, _)| (Vec3::new(mob.pos.x - point.x, 0., mob.pos.z - point.z).length(), entity))
+                .filter(|(distance, _)| *distance < ATTACK_PICK_RADIUS)
+                .min_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+
+            match nearest {
+                Some((_, entity)) => self.attack_target = Some(entity),
+                None => self.target_position = point
+            }
+        }
+
+        if let Some(target) = self.attack_target {
+            match mobs.get(target) {
+                Ok((_, mob, health)) if !health.is_dead() => {
+                    let mut offset = self.pos - mob.pos;
+                    offset.y = 0.;
+                    self.target_position = mob.pos + offset.normalize_or_zero() * ATTACK_RANGE.min(offset.length());
+                }
+                _ => self.attack_target = None
+            }
+            return;
+        }
+
+        let mut offset = self.target_position - self.pos;
+        offset.y = 0.;
+        if self.move_direction.is_none() && offset.length() < WAYPOINT_RADIUS && !self.waypoints.is_empty() {
+            self.target_position = self.waypoints.remove(0);
+        }
+    }
+
+    /// Use `ability` if it is off cooldown. Abilities can't be used in the air.
+    pub fn use_ability(&mut self, ability: Ability) {
+        if self.airborne || self.last_jump.elapsed_secs() < ability.cooldown() {
+            return;
+        }
+
+        match ability {
+            Ability::Jump => {
+                self.airborne = true;
+            }
+            Ability::Dash => {
+                let mut direction = self.move_direction.unwrap_or(self.target_position - self.pos);
+                direction.y = 0.;
+                let direction = direction.try_normalize()
+                    .or_else(|| Vec3::new(self.vel.x, 0., self.vel.z).try_normalize())
+                    .unwrap_or(Vec3::X);
+
+                self.vel += direction * DASH_SPEED;
+            }
+        }
+
+        self.last_jump.reset();
+    }
+
     pub fn update(
         &mut self,
-        dt: f32
+        dt: f32,
+        terrain: &Terrain
     ) {
         // Get current direction and slow down
         let mut cd = 0.04;
 
-        let difference = (self.target_position - self.pos);
+        let mut difference = (self.target_position - self.pos);
+        difference.y = 0.;
         let length = difference.length_squared();
 
-        self.acc = difference;
+        self.acc = difference * self.speed;
+
+        // Held directions steer towards a velocity instead, which also does the slowing down.
+        if let Some(direction) = self.move_direction {
+            let horizontal = Vec3::new(self.vel.x, 0., self.vel.z);
+            self.acc = (direction * DIRECT_SPEED * self.speed - horizontal) * DIRECT_RESPONSE;
+        }
+
+        if self.airborne {
+            // Only a little steering in the air, split along and across the direction of travel.
+            let forward = Vec3::new(self.vel.x, 0., self.vel.z).normalize_or_zero();
+            let along = forward * self.acc.dot(forward);
+            self.acc = (along + (self.acc - along) * SIDE_SPEED_FACTOR) * SPEED;
+
+            // Pushed up for the first moments of a jump, then pulled back down.
+            self.acc.y = GRAVITY;
+            if self.last_jump.elapsed_secs() < JUMP_TIMER {
+                self.acc.y += JUMP_ACCEL;
+            }
+        }
 
         self.vel += self.acc * dt;
 
         cd = (1.0 - cd) / (1.0 + length) + cd;
 
-        self.vel -= cd * self.vel * self.vel.length() * dt;
+        // Drag only slows the player across the ground, and only when walking to the target.
+        if self.move_direction.is_none() {
+            let horizontal = Vec3::new(self.vel.x, 0., self.vel.z);
+            self.vel -= cd * horizontal * horizontal.length() * dt;
+        }
 
         self.pos += self.vel * dt;
-        self.pos.y = 0.5;
+
+        let ground = terrain.height(self.pos.x, self.pos.z) + PLAYER_SIZE * 0.5;
+        if !self.airborne || self.pos.y <= ground {
+            self.pos.y = ground;
+            self.vel.y = 0.;
+            self.airborne = false;
+        }
     }
 }
 
 #[derive(Component)]
 pub struct Projectile {
+    /// The player that fired it.
+    pub source: Entity,
+    /// Kind of the weapon that fired it.
+    pub weapon: WeaponKind,
     pub pos: Vec3,
     pub vel: Vec3,
     pub acc: Vec3,
     pub damage: f32,
+    /// Acceleration towards the target, 0 flies straight.
+    pub homing: f32,
+    pub targeting: Targeting,
+    /// Mob currently homed in on.
+    pub target: Option<Entity>,
+    /// Mobs it can still go through after the next hit.
+    pub pierce: usize,
+    /// Mobs already hit, so a piercing projectile only hits each once.
+    pub hits: Vec<Entity>,
     pub lifetime: Timer
 }
 
 impl Projectile {
     pub fn update(
         &mut self,
-        dt: f32
+        dt: f32,
+        terrain: &Terrain
     ) {
         // Get current direction and slow down
         let cd = 0.04;
 
-        self.vel += self.acc * dt * PROJECTILE_SPEED;
-        self.vel -= cd * self.vel * self.vel.length() * dt;
+        self.acc.y = 0.;
+        self.vel += self.acc * dt * self.homing;
+
+        // Drag keeps homing projectiles from speeding up forever, the rest fly straight.
+        if self.homing > 0. {
+            self.vel -= cd * self.vel * self.vel.length() * dt;
+        }
 
         self.pos += self.vel * dt;
-        self.pos.y = 0.5;
+        self.pos.y = terrain.height(self.pos.x, self.pos.z) + 0.5;
     }
 }
 
+/// Marker for where the player is walking to.
 #[derive(Component)]
-pub struct ProjectileTimer {
-    timer: Timer,
-}
+pub struct Target;
 
+/// Marker for a queued waypoint, by its place in the queue.
 #[derive(Component)]
-pub struct Target;
+pub struct Waypoint(pub usize);
 
 pub fn spawn_player(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    terrain: Res<Terrain>
 ) {
+    let position = Vec3::new(0.0, terrain.height(0.0, 0.0) + PLAYER_SIZE * 0.5, 0.0);
+
     commands.spawn(PbrBundle {
-        mesh: meshes.add(Mesh::from(shape::Cube { size: 1.0 })),
+        mesh: meshes.add(Mesh::from(shape::Cube { size: PLAYER_SIZE })),
         material: materials.add(PLAYER_COLOUR.into()),
-        transform: Transform::from_xyz(0.0, 0.5, 0.0),
+        transform: Transform::from_translation(position),
         ..default()
     }).insert(Player {
-        pos: Vec3::new(0.0, 0.5, 0.0),
+        pos: position,
         vel: Vec3::default(),
         acc: Vec3::default(),
-        health: 100.0,
-        target_position: Vec3::new(0.0, 0.5, 0.0),
+        speed: 1.0,
+        target_position: position,
+        last_jump: ready_stopwatch(),
         ..default()
-    }).insert(ProjectileTimer {
-        timer: Timer::new(Duration::from_secs_f32(PROJECTILE_SPAWN_RATE), TimerMode::Repeating)
+    }).insert(
+        Health::new(PLAYER_HEALTH)
+            .with_regen(PLAYER_REGEN)
+            .with_armor(PLAYER_ARMOR)
+            .with_invulnerability(PLAYER_INVULNERABILITY)
+    ).with_children(|parent| {
+        parent.spawn(Weapon::new(STARTING_WEAPON));
     });
 
     commands.spawn(PbrBundle {
         mesh: meshes.add(Mesh::from(shape::Cube { size: 0.3 })),
         material: materials.add(TARGET_COLOUR.into()),
-        transform: Transform::from_xyz(0.0, 0.5, 0.0),
+        transform: Transform::from_translation(position),
         ..default()
     }).insert(Target {
     });
 }
 
-pub fn player_control(
-    time: Res<Time>,
-    mut camera: Query<(&mut Transform), (With<GameCamera>, Without<Player>)>,
-    mut player_query: Query<(Entity, &mut Transform, &mut Player, &mut ProjectileTimer), With<Player>>
-) {
-    for (entity, mut transform, mut player, mut timer) in player_query.iter_mut() {
-        player.update(time.delta_seconds());
-        transform.translation = player.pos;
+/// A stopwatch that has been running long enough for every ability to be off cooldown.
+fn ready_stopwatch() -> Stopwatch {
+    let mut stopwatch = Stopwatch::new();
+    stopwatch.set_elapsed(Duration::from_secs_f32(JUMP_COOLDOWN.max(DASH_COOLDOWN)));
+    stopwatch
+}
 
-        for (mut camera_transform) in camera.iter_mut() {
-            *camera_transform = Transform::from_translation(
-                    Vec3::new(CAMERA_DISTANCE / 1.5,
-                             1.0 * CAMERA_DISTANCE,
-                             CAMERA_DISTANCE / 1.5) + transform.translation
-                )
-                .looking_at(transform.translation, Vec3::Y)
-        }
+/// Remove the player with their weapons, their projectiles and the target marker at the end of
+/// a run.
+fn despawn_run(
+    mut commands: Commands,
+    entities: Query<Entity, Or<(With<Player>, With<Projectile>, With<Blade>, With<WeaponEffect>, With<Target>, With<Waypoint>)>>
+) {
+    for entity in entities.iter() {
+        commands.entity(entity).despawn_recursive();
     }
 }
 
-fn handle_mouse_clicks(
+pub fn player_control(
+    step: Res<FixedTimestepInfo>,
+    terrain: Res<Terrain>,
     mut player_query: Query<(Entity, &mut Transform, &mut Player), With<Player>>,
-    mut target: Query<&mut Transform, (With<Target>, Without<Player>)>,
-    mouse_input: Res<Input<MouseButton>>,
-    to: Query<&RaycastSource<Surface>>,
+    mobs: Query<(Entity, &Mob, &Health)>
 ) {
-    if let Ok(raycast_source) = to.get_single() {
-        if let Some(top_intersection) = raycast_source.get_nearest_intersection() {
-            let mut new_position = top_intersection.1.position();
-            new_position.y = 0.5;
-            if mouse_input.just_pressed(MouseButton::Left) {
-                for (entity, mut transform, mut player) in player_query.iter_mut() {
-                    player.target_position = new_position;
-                    for mut transform in target.iter_mut() {
-                        transform.translation = new_position;
-                    }
-                }
-            }
+    for (entity, mut transform, mut player) in player_query.iter_mut() {
+        player.follow_orders(&mobs);
+
+        player.last_jump.tick(step.timestep());
+        if let Some(ability) = player.ability.take() {
+            player.use_ability(ability);
         }
+
+        player.update(step.timestep().as_secs_f32(), &terrain);
+        transform.translation = player.pos;
     }
 }
 
-fn projectile_spawner(
+/// Keep the target marker on where the player is walking to, with another marker for every
+/// queued waypoint.
+fn update_target_markers(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
-    time: Res<Time>,
-    mut player_query: Query<(Entity, &mut Transform, &mut Player, &mut ProjectileTimer), With<Player>>
+    players: Query<&Player>,
+    mut target: Query<(&mut Transform, &mut Visibility), (With<Target>, Without<Waypoint>)>,
+    mut waypoints: Query<(Entity, &Waypoint, &mut Transform), Without<Target>>
 ) {
-    for (entity, mut transform, mut player, mut timer) in player_query.iter_mut() {
-        timer.timer.tick(time.delta());
-        if timer.timer.finished() {
+    let player = match players.get_single() {
+        Ok(player) => player,
+        Err(_) => return
+    };
+
+    for (mut transform, mut visibility) in target.iter_mut() {
+        transform.translation = player.target_position;
+        visibility.is_visible = player.move_direction.is_none();
+    }
+
+    let mut shown = vec![false; player.waypoints.len()];
+    for (entity, waypoint, mut transform) in waypoints.iter_mut() {
+        match player.waypoints.get(waypoint.0) {
+            Some(position) => {
+                transform.translation = *position;
+                shown[waypoint.0] = true;
+            }
+            None => commands.entity(entity).despawn()
+        }
+    }
+
+    for (index, position) in player.waypoints.iter().enumerate() {
+        if !shown[index] {
             commands.spawn(PbrBundle {
                 mesh: meshes.add(Mesh::from(shape::Cube { size: 0.3 })),
-                material: materials.add(PLAYER_COLOUR.into()),
-                transform: Transform::from_translation(transform.translation),
+                material: materials.add(TARGET_COLOUR.into()),
+                transform: Transform::from_translation(*position),
                 ..default()
-            }).insert(Projectile {
-                pos: transform.translation,
-                vel: Vec3::default(),
-                acc: Vec3::default(),
-                damage: 3.0,
-                lifetime: Timer::new(Duration::from_secs_f32(PROJECTILE_LIFETIME), TimerMode::Once),
-            });
+            }).insert(Waypoint(index));
         }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,128 @@
+use bevy::{
+    prelude::*,
+};
+use bevy_mod_raycast::RaycastSource;
+use iyes_loopless::prelude::*;
+use crate::GameState;
+use crate::camera::GameCamera;
+use crate::input::{Action, ActionState, Device};
+use crate::player::{Ability, FOLLOW_MIN_DISTANCE, MoveOrder, Player};
+use crate::replay::{ReplayPlayback, ReplayRecorder};
+use crate::simulation::RunStats;
+use crate::surface::Surface;
+
+/// Mouse, keyboard and gamepad control of the player, kept apart from `PlayerPlugin` so the player
+/// can run without a window or raycasting. Left out while a replay is being played back. Reads the
+/// `ActionState`, so the `ActionsPlugin` has to be added as well.
+pub struct PlayerInputPlugin;
+impl Plugin for PlayerInputPlugin {
+    fn build(&self, app: &mut App) {
+        if app.world.contains_resource::<ReplayPlayback>() {
+            return;
+        }
+
+        app.add_system_set(
+            ConditionSet::new()
+                .run_in_state(GameState::InGame)
+                .with_system(handle_mouse_clicks)
+                .with_system(handle_movement)
+                .with_system(handle_abilities)
+                .into()
+        );
+    }
+}
+
+fn handle_mouse_clicks(
+    mut player_query: Query<&mut Player>,
+    actions: Res<ActionState>,
+    to: Query<&RaycastSource<Surface>>,
+    mut recorder: Option<ResMut<ReplayRecorder>>,
+    stats: Res<RunStats>
+) {
+    if let Ok(raycast_source) = to.get_single() {
+        if let Some(top_intersection) = raycast_source.get_nearest_intersection() {
+            // Sit the target marker on the ground where the cursor hits it.
+            let mut new_position = top_intersection.1.position();
+            new_position.y += 0.15;
+
+            for mut player in player_query.iter_mut() {
+                // Holding the button keeps the target on the cursor, as long as there is no queue
+                // or attack for it to throw away.
+                let following = actions.pressed(Action::MoveTo)
+                    && !actions.pressed(Action::Queue)
+                    && player.move_direction.is_none()
+                    && player.waypoints.is_empty()
+                    && player.attack_target.is_none()
+                    && player.target_position.distance(new_position) > FOLLOW_MIN_DISTANCE;
+
+                let order = if actions.just_pressed(Action::AttackMove) {
+                    MoveOrder::Attack
+                } else if actions.just_pressed(Action::MoveTo) && actions.pressed(Action::Queue) {
+                    MoveOrder::Queue
+                } else if actions.just_pressed(Action::MoveTo) || following {
+                    MoveOrder::Move
+                } else {
+                    continue;
+                };
+
+                player.order(order, new_position);
+
+                // The next tick to run is the first one to see the new target.
+                if let Some(recorder) = recorder.as_mut() {
+                    recorder.record(stats.ticks, new_position, order);
+                }
+            }
+        }
+    }
+}
+
+/// Move the player in the direction held on the keyboard or a gamepad, relative to the camera.
+fn handle_movement(
+    actions: Res<ActionState>,
+    camera: Query<&GameCamera>,
+    mut player_query: Query<&mut Player>,
+    mut recorder: Option<ResMut<ReplayRecorder>>,
+    stats: Res<RunStats>
+) {
+    if actions.device == Device::Mouse {
+        return;
+    }
+
+    // Up on the screen is away from the camera, taken from the quarter it is turning to.
+    let (up, right) = camera.get_single().map_or((Vec3::NEG_Z, Vec3::X), GameCamera::ground_axes);
+    let direction = up * actions.movement.y + right * actions.movement.x;
+
+    for mut player in player_query.iter_mut() {
+        if player.move_direction == Some(direction) {
+            continue;
+        }
+        player.move_direction = Some(direction);
+
+        if let Some(recorder) = recorder.as_mut() {
+            recorder.record_direction(stats.ticks, direction);
+        }
+    }
+}
+
+fn handle_abilities(
+    actions: Res<ActionState>,
+    mut player_query: Query<&mut Player>,
+    mut recorder: Option<ResMut<ReplayRecorder>>,
+    stats: Res<RunStats>
+) {
+    let ability = if actions.just_pressed(Action::Jump) {
+        Ability::Jump
+    } else if actions.just_pressed(Action::Dash) {
+        Ability::Dash
+    } else {
+        return;
+    };
+
+    for mut player in player_query.iter_mut() {
+        player.ability = Some(ability);
+    }
+
+    if let Some(recorder) = recorder.as_mut() {
+        recorder.record_ability(stats.ticks, ability);
+    }
+}
@@ -0,0 +1,323 @@
+use bevy::{
+    prelude::*,
+};
+use iyes_loopless::prelude::*;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use crate::{GameState, run_starting};
+use crate::health::Health;
+use crate::mob::Mob;
+use crate::player::{Player, PlayerLabel};
+use crate::replay::{ReplayPlayback, ReplayRecorder};
+use crate::rng::SimulationRng;
+use crate::simulation::{RunStats, SIMULATION_TIMESTEP, SimulationLabel, STEP_INTEGRATE, STEP_PREPARE};
+use crate::terrain::Terrain;
+use crate::weapon::{Weapon, WeaponKind};
+
+pub const XP_ORB_COLOR: Color = Color::rgb(0.3, 0.9, 0.5);
+/// Experience dropped per point of mob strength.
+pub const XP_PER_STRENGTH: f32 = 1.0;
+/// Orbs closer to the player than this are pulled in.
+pub const XP_MAGNET_RADIUS: f32 = 4.0;
+pub const XP_PICKUP_RADIUS: f32 = 0.8;
+pub const XP_ORB_SPEED: f32 = 10.0;
+
+/// Experience needed for the second level, each level after needs `XP_GROWTH` times more.
+pub const XP_BASE: f32 = 10.0;
+pub const XP_GROWTH: f32 = 1.3;
+
+/// Upgrades on offer at each level up.
+pub const UPGRADE_CHOICES: usize = 3;
+pub const DAMAGE_UPGRADE: f32 = 1.2;
+pub const FIRE_RATE_UPGRADE: f32 = 1.15;
+pub const MOVE_SPEED_UPGRADE: f32 = 1.1;
+pub const MAX_HEALTH_UPGRADE: f32 = 20.0;
+
+/// Weights of the upgrades in the pool. Every weapon the player doesn't hold yet is in there
+/// too, with weight `NEW_WEAPON_WEIGHT`.
+const UPGRADE_POOL: [(Upgrade, f32); 4] = [
+    (Upgrade::Damage, 3.0),
+    (Upgrade::FireRate, 3.0),
+    (Upgrade::MoveSpeed, 2.0),
+    (Upgrade::MaxHealth, 2.0),
+];
+const NEW_WEAPON_WEIGHT: f32 = 1.0;
+
+const WEAPON_KINDS: [WeaponKind; 5] = [
+    WeaponKind::Seeker,
+    WeaponKind::SpreadShot,
+    WeaponKind::OrbitingBlades,
+    WeaponKind::AreaPulse,
+    WeaponKind::Beam,
+];
+
+pub struct ProgressionPlugin;
+impl Plugin for ProgressionPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Experience>()
+            .init_resource::<PendingUpgrades>()
+            .add_enter_system(GameState::InGame, reset_experience.run_if(run_starting))
+            .add_exit_system(GameState::GameOver, despawn_orbs)
+            .add_enter_system(GameState::MainMenu, despawn_orbs)
+            // Chosen upgrades are applied whatever the state, so they land on the same tick when
+            // a replay of the run feeds them back in.
+            .add_fixed_timestep_system_set(
+                SIMULATION_TIMESTEP,
+                STEP_PREPARE,
+                ConditionSet::new()
+                    .label(PlayerLabel::Upgrades)
+                    .before(PlayerLabel::Control)
+                    .with_system(apply_upgrades)
+                    .into()
+            ).add_fixed_timestep_system_set(
+                SIMULATION_TIMESTEP,
+                STEP_INTEGRATE,
+                ConditionSet::new()
+                    .run_in_state(GameState::InGame)
+                    .after(SimulationLabel::Projectiles)
+                    .with_system(collect_experience)
+                    .into()
+            );
+    }
+}
+
+/// Dropped by mobs when they die, worth `value` experience once the player picks it up.
+#[derive(Component)]
+pub struct XpOrb {
+    pub pos: Vec3,
+    pub value: f32
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Upgrade {
+    Damage,
+    FireRate,
+    MoveSpeed,
+    MaxHealth,
+    NewWeapon(WeaponKind),
+}
+
+impl Upgrade {
+    pub fn describe(&self) -> String {
+        match self {
+            Upgrade::Damage => format!("+{:.0}% damage", (DAMAGE_UPGRADE - 1.0) * 100.0),
+            Upgrade::FireRate => format!("+{:.0}% fire rate", (FIRE_RATE_UPGRADE - 1.0) * 100.0),
+            Upgrade::MoveSpeed => format!("+{:.0}% move speed", (MOVE_SPEED_UPGRADE - 1.0) * 100.0),
+            Upgrade::MaxHealth => format!("+{:.0} max health", MAX_HEALTH_UPGRADE),
+            Upgrade::NewWeapon(kind) => format!("new weapon: {:?}", kind),
+        }
+    }
+}
+
+#[derive(Resource)]
+pub struct Experience {
+    pub level: usize,
+    /// Experience towards the next level.
+    pub xp: f32,
+    /// Level ups not spent on an upgrade yet.
+    pub pending: usize,
+    /// Upgrades to pick from for the next pending level, empty while none are on offer.
+    pub offer: Vec<Upgrade>
+}
+
+impl Default for Experience {
+    fn default() -> Self {
+        Experience {
+            level: 1,
+            xp: 0.0,
+            pending: 0,
+            offer: Vec::new()
+        }
+    }
+}
+
+impl Experience {
+    /// Experience needed to go from the current level to the next.
+    pub fn next_level(&self) -> f32 {
+        XP_BASE * XP_GROWTH.powi(self.level as i32 - 1)
+    }
+}
+
+/// Upgrades that have been picked and are waiting to be applied on the next tick.
+#[derive(Resource, Default)]
+pub struct PendingUpgrades(pub Vec<Upgrade>);
+
+/// Take upgrade `index` of the current offer and go back to the run.
+pub fn choose_upgrade(
+    commands: &mut Commands,
+    experience: &Experience,
+    pending: &mut PendingUpgrades,
+    index: usize
+) {
+    if let Some(upgrade) = experience.offer.get(index) {
+        pending.0.push(*upgrade);
+    }
+
+    commands.insert_resource(NextState(GameState::InGame));
+}
+
+pub fn spawn_xp_orb(
+    commands: &mut Commands,
+    meshes: &mut ResMut<Assets<Mesh>>,
+    materials: &mut ResMut<Assets<StandardMaterial>>,
+    position: Vec3,
+    value: f32
+) {
+    commands.spawn(PbrBundle {
+        mesh: meshes.add(Mesh::from(shape::Icosphere { radius: 0.2, subdivisions: 1 })),
+        material: materials.add(XP_ORB_COLOR.into()),
+        transform: Transform::from_translation(position),
+        ..default()
+    }).insert(XpOrb {
+        pos: position,
+        value
+    });
+}
+
+/// Draw `UPGRADE_CHOICES` different upgrades from the weighted pool.
+fn draw_offer(
+    prng: &mut impl Rng,
+    held: &[WeaponKind]
+) -> Vec<Upgrade> {
+    let mut pool: Vec<(Upgrade, f32)> = UPGRADE_POOL.to_vec();
+    for kind in WEAPON_KINDS {
+        if !held.contains(&kind) {
+            pool.push((Upgrade::NewWeapon(kind), NEW_WEAPON_WEIGHT));
+        }
+    }
+
+    let mut offer = Vec::new();
+    while offer.len() < UPGRADE_CHOICES && !pool.is_empty() {
+        let total: f32 = pool.iter().map(|(_, weight)| weight).sum();
+        let mut roll = prng.gen::<f32>() * total;
+
+        let index = pool.iter()
+            .position(|(_, weight)| {
+                roll -= weight;
+                roll < 0.
+            })
+            .unwrap_or(pool.len() - 1);
+
+        offer.push(pool.remove(index).0);
+    }
+
+    offer
+}
+
+fn reset_experience(
+    mut experience: ResMut<Experience>,
+    mut pending: ResMut<PendingUpgrades>
+) {
+    *experience = Experience::default();
+    pending.0.clear();
+}
+
+fn despawn_orbs(
+    mut commands: Commands,
+    orbs: Query<Entity, With<XpOrb>>
+) {
+    for entity in orbs.iter() {
+        commands.entity(entity).despawn();
+    }
+}
+
+/// Pull in and pick up orbs near the player, and put upgrades on offer for every level gained.
+fn collect_experience(
+    mut commands: Commands,
+    step: Res<FixedTimestepInfo>,
+    terrain: Res<Terrain>,
+    mut rng: ResMut<SimulationRng>,
+    mut experience: ResMut<Experience>,
+    playback: Option<Res<ReplayPlayback>>,
+    players: Query<(&Player, &Health)>,
+    weapons: Query<&Weapon>,
+    mut orbs: Query<(Entity, &mut Transform, &mut XpOrb), Without<Mob>>
+) {
+    let dt = step.timestep().as_secs_f32();
+
+    // A dead player is on the way to the game over screen, which a level up mustn't replace when
+    // several ticks run in one frame.
+    let player_position = match players.iter().next() {
+        Some((player, health)) if !health.is_dead() => player.pos,
+        _ => return
+    };
+
+    for (entity, mut transform, mut orb) in orbs.iter_mut() {
+        let mut offset = player_position - orb.pos;
+        offset.y = 0.;
+        let distance = offset.length();
+
+        if distance < XP_PICKUP_RADIUS {
+            experience.xp += orb.value;
+            commands.entity(entity).despawn();
+            continue;
+        }
+
+        if distance < XP_MAGNET_RADIUS {
+            orb.pos += offset / distance * (XP_ORB_SPEED * dt).min(distance);
+            orb.pos.y = terrain.height(orb.pos.x, orb.pos.z) + 0.3;
+            transform.translation = orb.pos;
+        }
+    }
+
+    while experience.xp >= experience.next_level() {
+        experience.xp -= experience.next_level();
+        experience.level += 1;
+        experience.pending += 1;
+    }
+
+    if experience.pending > 0 && experience.offer.is_empty() {
+        let held: Vec<WeaponKind> = weapons.iter().map(|weapon| weapon.kind).collect();
+        experience.offer = draw_offer(rng.rng(), &held);
+
+        // A replay brings its own choices, so it plays on without stopping.
+        if playback.is_none() {
+            commands.insert_resource(NextState(GameState::LevelUp));
+        }
+    }
+}
+
+/// Apply the upgrades that have been picked, recording them for the replay.
+fn apply_upgrades(
+    mut commands: Commands,
+    mut pending: ResMut<PendingUpgrades>,
+    mut experience: ResMut<Experience>,
+    mut recorder: Option<ResMut<ReplayRecorder>>,
+    stats: Res<RunStats>,
+    mut players: Query<(Entity, &mut Player, &mut Health)>,
+    mut weapons: Query<&mut Weapon>
+) {
+    for upgrade in pending.0.drain(..) {
+        for (entity, mut player, mut health) in players.iter_mut() {
+            match upgrade {
+                Upgrade::Damage => {
+                    for mut weapon in weapons.iter_mut() {
+                        weapon.damage *= DAMAGE_UPGRADE;
+                    }
+                }
+                Upgrade::FireRate => {
+                    for mut weapon in weapons.iter_mut() {
+                        let fire_rate = weapon.fire_rate * FIRE_RATE_UPGRADE;
+                        weapon.set_fire_rate(fire_rate);
+                    }
+                }
+                Upgrade::MoveSpeed => player.speed *= MOVE_SPEED_UPGRADE,
+                Upgrade::MaxHealth => {
+                    health.max += MAX_HEALTH_UPGRADE;
+                    health.heal(MAX_HEALTH_UPGRADE);
+                }
+                Upgrade::NewWeapon(kind) => {
+                    let weapon = commands.spawn(Weapon::new(kind)).id();
+                    commands.entity(entity).add_child(weapon);
+                }
+            }
+        }
+
+        experience.pending = experience.pending.saturating_sub(1);
+        experience.offer.clear();
+
+        if let Some(recorder) = recorder.as_mut() {
+            recorder.record_upgrade(stats.ticks, upgrade);
+        }
+    }
+}
@@ -0,0 +1,281 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use bevy::{
+    prelude::*,
+};
+use iyes_loopless::prelude::*;
+use serde::{Deserialize, Serialize};
+use crate::{GameState, run_starting};
+use crate::player::{Ability, MoveOrder, Player, PlayerLabel};
+use crate::progression::{PendingUpgrades, Upgrade};
+use crate::rng::SimulationRng;
+use crate::simulation::{RunStats, SIMULATION_TIMESTEP, STEP_PREPARE, TickRate, Wave};
+
+/// Folder finished runs are written to, relative to the working directory.
+pub const REPLAY_FOLDER: &str = "replays";
+
+/// Command line flag to watch a replay instead of playing, e.g. `--replay replays/run.json`.
+pub const REPLAY_ARG: &str = "--replay";
+
+/// Everything needed to reproduce a run: the seed, the tick rate, and every target, held direction,
+/// ability and upgrade the player picked along with the simulation tick it took effect on.
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct Replay {
+    pub seed: u64,
+    pub tick_rate: f64,
+    pub targets: Vec<ReplayTarget>,
+    #[serde(default)]
+    pub directions: Vec<ReplayDirection>,
+    #[serde(default)]
+    pub abilities: Vec<ReplayAbility>,
+    #[serde(default)]
+    pub upgrades: Vec<ReplayUpgrade>,
+    /// How the recorded run ended, for checking a playback against.
+    pub result: Option<ReplayResult>
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub struct ReplayTarget {
+    pub tick: u64,
+    pub position: [f32; 3],
+    #[serde(default)]
+    pub order: MoveOrder
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub struct ReplayDirection {
+    pub tick: u64,
+    pub direction: [f32; 3]
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub struct ReplayAbility {
+    pub tick: u64,
+    pub ability: Ability
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub struct ReplayUpgrade {
+    pub tick: u64,
+    pub upgrade: Upgrade
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub struct ReplayResult {
+    pub waves: usize,
+    pub kills: usize,
+    pub ticks: u64
+}
+
+impl Replay {
+    pub fn load(
+        path: &Path
+    ) -> Result<Replay, String> {
+        let contents = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        serde_json::from_str(&contents).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn save(
+        &self,
+        path: &Path
+    ) -> Result<(), String> {
+        if let Some(folder) = path.parent() {
+            fs::create_dir_all(folder).map_err(|e| format!("{}: {}", folder.display(), e))?;
+        }
+
+        let contents = serde_json::to_string(self).map_err(|e| e.to_string())?;
+        fs::write(path, contents).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+}
+
+/// Read the replay to watch from the command line, if there is one.
+pub fn replay_from_args() -> Option<Replay> {
+    let args: Vec<String> = std::env::args().collect();
+    let path = args.iter().position(|arg| arg == REPLAY_ARG).and_then(|i| args.get(i + 1))?;
+
+    match Replay::load(Path::new(path)) {
+        Ok(replay) => Some(replay),
+        Err(e) => {
+            eprintln!("could not load replay {}", e);
+            None
+        }
+    }
+}
+
+/// The run being recorded, filled in by the input handlers.
+#[derive(Resource, Default)]
+pub struct ReplayRecorder(pub Replay);
+
+impl ReplayRecorder {
+    pub fn record(
+        &mut self,
+        tick: u64,
+        position: Vec3,
+        order: MoveOrder
+    ) {
+        self.0.targets.push(ReplayTarget { tick, position: position.to_array(), order });
+    }
+
+    pub fn record_direction(
+        &mut self,
+        tick: u64,
+        direction: Vec3
+    ) {
+        self.0.directions.push(ReplayDirection { tick, direction: direction.to_array() });
+    }
+
+    pub fn record_ability(
+        &mut self,
+        tick: u64,
+        ability: Ability
+    ) {
+        self.0.abilities.push(ReplayAbility { tick, ability });
+    }
+
+    pub fn record_upgrade(
+        &mut self,
+        tick: u64,
+        upgrade: Upgrade
+    ) {
+        self.0.upgrades.push(ReplayUpgrade { tick, upgrade });
+    }
+}
+
+/// A replay being played back in place of the player's input.
+#[derive(Resource)]
+pub struct ReplayPlayback {
+    pub replay: Replay,
+    next: usize,
+    next_direction: usize,
+    next_ability: usize,
+    next_upgrade: usize
+}
+
+impl ReplayPlayback {
+    pub fn new(replay: Replay) -> Self {
+        ReplayPlayback {
+            replay,
+            next: 0,
+            next_direction: 0,
+            next_ability: 0,
+            next_upgrade: 0
+        }
+    }
+}
+
+/// Records every run, or plays one back when a `ReplayPlayback` resource is inserted before this
+/// plugin is added. The seed and tick rate of the replay have to be used for the app as well.
+pub struct ReplayPlugin;
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut App) {
+        if app.world.contains_resource::<ReplayPlayback>() {
+            app.add_enter_system(GameState::InGame, rewind_playback.run_if(run_starting))
+                .add_fixed_timestep_system_set(
+                    SIMULATION_TIMESTEP,
+                    STEP_PREPARE,
+                    ConditionSet::new()
+                        .run_in_state(GameState::InGame)
+                        .before(PlayerLabel::Upgrades)
+                        .before(PlayerLabel::Control)
+                        .with_system(play_replay)
+                        .into()
+                );
+        } else {
+            app.init_resource::<ReplayRecorder>()
+                .add_enter_system(GameState::InGame, start_recording.run_if(run_starting))
+                .add_enter_system(GameState::GameOver, save_recording);
+        }
+    }
+}
+
+fn start_recording(
+    mut recorder: ResMut<ReplayRecorder>,
+    rng: Res<SimulationRng>,
+    tick_rate: Res<TickRate>
+) {
+    recorder.0 = Replay {
+        seed: rng.seed,
+        tick_rate: tick_rate.0,
+        ..default()
+    };
+}
+
+fn save_recording(
+    mut recorder: ResMut<ReplayRecorder>,
+    stats: Res<RunStats>,
+    wave: Res<Wave>
+) {
+    recorder.0.result = Some(ReplayResult {
+        waves: wave.current,
+        kills: stats.kills,
+        ticks: stats.ticks
+    });
+
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs());
+    let path = PathBuf::from(REPLAY_FOLDER).join(format!("{}-{}.json", timestamp, recorder.0.seed));
+
+    match recorder.0.save(&path) {
+        Ok(()) => info!("saved replay to {}", path.display()),
+        Err(e) => warn!("could not save replay {}", e)
+    }
+}
+
+fn rewind_playback(mut playback: ResMut<ReplayPlayback>) {
+    playback.next = 0;
+    playback.next_direction = 0;
+    playback.next_ability = 0;
+    playback.next_upgrade = 0;
+}
+
+/// Feed the recorded targets, directions, abilities and upgrades to the player on the tick they were originally picked.
+pub fn play_replay(
+    mut playback: ResMut<ReplayPlayback>,
+    stats: Res<RunStats>,
+    mut upgrades: ResMut<PendingUpgrades>,
+    mut players: Query<&mut Player>
+) {
+    while let Some(recorded) = playback.replay.targets.get(playback.next).copied() {
+        if recorded.tick > stats.ticks {
+            break;
+        }
+
+        let position = Vec3::from_array(recorded.position);
+        for mut player in players.iter_mut() {
+            player.order(recorded.order, position);
+        }
+
+        playback.next += 1;
+    }
+
+    while let Some(recorded) = playback.replay.directions.get(playback.next_direction).copied() {
+        if recorded.tick > stats.ticks {
+            break;
+        }
+
+        for mut player in players.iter_mut() {
+            player.move_direction = Some(Vec3::from_array(recorded.direction));
+        }
+        playback.next_direction += 1;
+    }
+
+    while let Some(recorded) = playback.replay.abilities.get(playback.next_ability).copied() {
+        if recorded.tick > stats.ticks {
+            break;
+        }
+
+        for mut player in players.iter_mut() {
+            player.ability = Some(recorded.ability);
+        }
+        playback.next_ability += 1;
+    }
+
+    while let Some(recorded) = playback.replay.upgrades.get(playback.next_upgrade).copied() {
+        if recorded.tick > stats.ticks {
+            break;
+        }
+
+        upgrades.0.push(recorded.upgrade);
+        playback.next_upgrade += 1;
+    }
+}
\ No newline at end of file
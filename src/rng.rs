@@ -0,0 +1,59 @@
+use std::env;
+use bevy::{
+    prelude::*,
+};
+use rand::{Rng, SeedableRng, thread_rng};
+use rand::rngs::StdRng;
+
+/// Command line flag used to pick the seed of a run, e.g. `--seed 1234`.
+pub const SEED_ARG: &str = "--seed";
+
+/// The single source of randomness for the simulation.
+///
+/// Every random decision made by the game has to go through this resource, so that a seed plus
+/// the player's input reproduces a run exactly.
+#[derive(Resource)]
+pub struct SimulationRng {
+    pub seed: u64,
+    rng: StdRng
+}
+
+impl SimulationRng {
+    pub fn new(seed: u64) -> Self {
+        SimulationRng {
+            seed,
+            rng: StdRng::seed_from_u64(seed)
+        }
+    }
+
+    /// Rewind the generator to the start of the seed, ready for a new run.
+    pub fn reset(&mut self) {
+        self.rng = StdRng::seed_from_u64(self.seed);
+    }
+
+    pub fn rng(&mut self) -> &mut StdRng {
+        &mut self.rng
+    }
+}
+
+/// Read the seed from the command line, falling back to a random one.
+pub fn seed_from_args() -> u64 {
+    let args: Vec<String> = env::args().collect();
+
+    for (i, arg) in args.iter().enumerate() {
+        if arg == SEED_ARG {
+            if let Some(seed) = args.get(i + 1).and_then(|value| value.parse().ok()) {
+                return seed;
+            }
+
+            eprintln!("expected a number after {}, using a random seed", SEED_ARG);
+        }
+    }
+
+    thread_rng().gen()
+}
+
+/// Restart the generator at the beginning of every run.
+pub fn reset_rng(mut rng: ResMut<SimulationRng>) {
+    rng.reset();
+}
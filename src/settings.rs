@@ -0,0 +1,219 @@
+use std::fs;
+use std::path::PathBuf;
+use bevy::{
+    prelude::*,
+};
+use bevy::render::camera::{Projection, ScalingMode};
+use bevy::window::PresentMode;
+use serde::{Deserialize, Serialize};
+use crate::highscore::save_folder;
+use crate::input::InputBindings;
+use crate::camera::{DEFAULT_ZOOM, GameCamera, MAX_ZOOM, MIN_ZOOM};
+use crate::surface::CAMERA_DISTANCE;
+
+pub const SETTINGS_FILE: &str = "settings.ron";
+
+/// Sample counts the settings screen cycles through, the only ones every backend supports.
+pub const MSAA_SAMPLES: [u32; 2] = [1, 4];
+pub const MIN_CAMERA_DISTANCE: f32 = 8.0;
+pub const MAX_CAMERA_DISTANCE: f32 = 32.0;
+pub const CAMERA_DISTANCE_STEP: f32 = 4.0;
+pub const VOLUME_STEP: f32 = 0.1;
+
+/// Everything the player can change from the settings screen, kept in `settings.ron` in the
+/// save folder. Settings missing from the file take their defaults.
+#[derive(Resource, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Settings {
+    pub vsync: bool,
+    pub msaa: u32,
+    /// Height of the world in view, larger shows more of the arena.
+    pub camera_distance: f32,
+    /// Orthographic scale set with the mouse wheel, smaller is closer in.
+    pub zoom: f32,
+    /// Master volume from 0 to 1.
+    pub volume: f32,
+    pub bindings: InputBindings
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            vsync: true,
+            msaa: 1,
+            camera_distance: CAMERA_DISTANCE,
+            zoom: DEFAULT_ZOOM,
+            volume: 1.0,
+            bindings: InputBindings::default()
+        }
+    }
+}
+
+impl Settings {
+    pub fn path() -> PathBuf {
+        save_folder().join(SETTINGS_FILE)
+    }
+
+    /// Read the settings from disk, starting from the defaults if they are missing or unreadable.
+    pub fn load() -> Self {
+        let path = Settings::path();
+
+        let mut settings: Settings = match fs::read_to_string(&path) {
+            Ok(contents) => ron::from_str(&contents).unwrap_or_else(|e| {
+                warn!("could not read settings from {}: {}", path.display(), e);
+                Settings::default()
+            }),
+            Err(_) => Settings::default()
+        };
+        settings.bindings.fill_defaults();
+
+        settings
+    }
+
+    pub fn save(&self) {
+        let path = Settings::path();
+        let contents = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default()).unwrap();
+
+        let result = path.parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| fs::write(&path, contents));
+
+        if let Err(e) = result {
+            warn!("could not save settings to {}: {}", path.display(), e);
+        }
+    }
+
+    pub fn present_mode(&self) -> PresentMode {
+        if self.vsync {
+            PresentMode::AutoVsync
+        } else {
+            PresentMode::AutoNoVsync
+        }
+    }
+
+    /// Move on to the next MSAA sample count, wrapping around.
+    pub fn cycle_msaa(&mut self) {
+        let index = MSAA_SAMPLES.iter().position(|samples| *samples == self.msaa).unwrap_or(0);
+        self.msaa = MSAA_SAMPLES[(index + 1) % MSAA_SAMPLES.len()];
+    }
+
+    /// Zoom out to the next step, wrapping back to the closest distance past the furthest.
+    /// Distances between steps are snapped onto the next one.
+    pub fn step_camera_distance(&mut self) {
+        let steps = (self.camera_distance / CAMERA_DISTANCE_STEP).floor();
+        self.camera_distance = (steps + 1.0) * CAMERA_DISTANCE_STEP;
+        if self.camera_distance > MAX_CAMERA_DISTANCE + f32::EPSILON {
+            self.camera_distance = MIN_CAMERA_DISTANCE;
+        }
+    }
+
+    /// Turn the volume up a step, wrapping back to silent past full.
+    pub fn step_volume(&mut self) {
+        self.volume = ((self.volume / VOLUME_STEP).round() + 1.0) * VOLUME_STEP;
+        if self.volume > 1.0 + f32::EPSILON {
+            self.volume = 0.0;
+        }
+    }
+}
+
+/// Applies the `Settings` whenever they change. The window is created with them too, so they have
+/// to be inserted before the `DefaultPlugins` are added.
+pub struct SettingsPlugin;
+impl Plugin for SettingsPlugin {
+    fn build(&self, app: &mut App) {
+        if !app.world.contains_resource::<Settings>() {
+            app.insert_resource(Settings::load());
+        }
+
+        app.add_system(apply_settings);
+    }
+}
+
+fn apply_settings(
+    settings: Res<Settings>,
+    mut windows: ResMut<Windows>,
+    mut msaa: ResMut<Msaa>,
+    bindings: Option<ResMut<InputBindings>>,
+    mut cameras: Query<(&mut GameCamera, &mut Projection)>
+) {
+    if !settings.is_changed() {
+        return;
+    }
+
+    if let Some(window) = windows.get_primary_mut() {
+        if window.present_mode() != settings.present_mode() {
+            window.set_present_mode(settings.present_mode());
+        }
+    }
+
+    if msaa.samples != settings.msaa {
+        msaa.samples = settings.msaa;
+    }
+
+    if let Some(mut bindings) = bindings {
+        *bindings = settings.bindings.clone();
+    }
+
+    for (mut camera, mut projection) in cameras.iter_mut() {
+        camera.distance = settings.camera_distance;
+
+        if let Projection::Orthographic(orthographic) = &mut *projection {
+            orthographic.scaling_mode = ScalingMode::FixedVertical(settings.camera_distance);
+            orthographic.scale = settings.zoom.clamp(MIN_ZOOM, MAX_ZOOM);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn msaa_cycles_through_the_supported_counts() {
+        let mut settings = Settings::default();
+
+        settings.cycle_msaa();
+        assert_eq!(settings.msaa, 4);
+        settings.cycle_msaa();
+        assert_eq!(settings.msaa, 1);
+
+        // Counts from a hand edited file start the cycle over.
+        settings.msaa = 2;
+        settings.cycle_msaa();
+        assert_eq!(settings.msaa, 4);
+    }
+
+    #[test]
+    fn camera_distance_steps_from_the_default_and_wraps() {
+        // The default sits between steps, so the first press snaps it onto the next one.
+        let mut settings = Settings::default();
+        let mut seen = Vec::new();
+
+        for _ in 0..8 {
+            settings.step_camera_distance();
+            seen.push(settings.camera_distance);
+        }
+
+        assert_eq!(seen, vec![16.0, 20.0, 24.0, 28.0, 32.0, 8.0, 12.0, 16.0]);
+    }
+
+    #[test]
+    fn distances_between_steps_snap_onto_the_next() {
+        for (distance, next) in [(12.8, 16.0), (9.5, 12.0), (31.0, 32.0), (12.0, 16.0)] {
+            let mut settings = Settings { camera_distance: distance, ..default() };
+
+            settings.step_camera_distance();
+            assert_eq!(settings.camera_distance, next);
+        }
+    }
+
+    #[test]
+    fn volume_wraps_back_to_silent_past_full() {
+        let mut settings = Settings { volume: 0.9, ..default() };
+
+        settings.step_volume();
+        assert!((settings.volume - 1.0).abs() < 1e-4);
+        settings.step_volume();
+        assert_eq!(settings.volume, 0.0);
+    }
+}
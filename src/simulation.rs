@@ -1,32 +1,205 @@
 use std::collections::HashSet;
+use std::time::Duration;
 
 use bevy::{
     prelude::*,
 };
 use bevy::time::Stopwatch;
-use iyes_loopless::prelude::{ConditionSet, NextState};
-use rand::{Rng, thread_rng};
-use crate::GameState;
-use crate::mob::{ATTACKED_COLOR, ATTACKED_FLASH_TIME, get_mob_type, Mob};
-use crate::player::{Player, Projectile};
+use iyes_loopless::prelude::*;
+use rand::Rng;
+use crate::{GameState, run_starting};
+use crate::arena::{ArenaBounds, confine_to_arena};
+use crate::collision::resolve_collisions;
+use crate::flowfield::{FLOW_DIRECT_DISTANCE, FlowField, update_flow_field};
+use crate::combat_log::write_combat_log;
+use crate::health::{apply_damage, DamageDealtEvent, DamageEvent, DamageKind, DeathEvent, Health, player_death, update_health};
+use crate::mob::{ATTACKED_COLOR, ATTACKED_FLASH_TIME, Mob, MobArchetypes, MobBehaviour, MobBolt};
+use crate::obstacle::{obstacle_avoidance, ObstacleGrid, ObstacleLayout, update_obstacle_grid};
+use crate::player::{Player, PlayerLabel, Projectile};
+use crate::progression::{spawn_xp_orb, XP_PER_STRENGTH};
+use crate::rng::{reset_rng, SimulationRng};
+use crate::terrain::Terrain;
+use crate::weapon::Targeting;
+use crate::waves::{ActiveWaveTable, load_wave_table, WaveDefinition, WaveTable, WaveTableLoader};
+use crate::spatial::{inter_mob_force, rebuild_spatial_grid, SpatialGrid};
 
 pub const MAX_ATTRACTION_DISTANCE: f32 = 10.0;
+pub const SEPARATION_DISTANCE: f32 = 2.0;
+pub const SEPARATION_FORCE: f32 = 5.0;
 pub const PLAYER_SIZE: f32 = 1.0;
 
+pub const MOB_BOLT_SPEED: f32 = 8.0;
+pub const MOB_BOLT_LIFETIME: f32 = 3.0;
+/// Radius projectiles and mob bolts are tested against obstacles with.
+pub const PROJECTILE_RADIUS: f32 = 0.15;
+
+/// Label of the fixed timestep stage all of the game physics runs in.
+pub const SIMULATION_TIMESTEP: &str = "simulation_timestep";
+pub const DEFAULT_TICK_RATE: f64 = 60.0;
+
+/// Sub-stages of the simulation timestep, run in order every tick.
+/// Commands are applied between them, so entities spawned in one tick are seen by the next.
+pub const STEP_PREPARE: usize = 0;
+pub const STEP_FORCES: usize = 1;
+pub const STEP_INTEGRATE: usize = 2;
+pub const STEP_COLLIDE: usize = 3;
+pub const STEP_CONFINE: usize = 4;
+
+#[derive(SystemLabel)]
+pub enum SimulationLabel {
+    InterMobForces,
+    PlayerMobForces,
+    Confine,
+    Damage,
+    Mobs,
+    Projectiles,
+}
+
+/// Number of simulation ticks per second, independent of the render frame rate.
+/// Insert before adding the `SimulationPlugin` to change it.
+#[derive(Resource, Clone, Copy)]
+pub struct TickRate(pub f64);
+
+impl Default for TickRate {
+    fn default() -> Self {
+        TickRate(DEFAULT_TICK_RATE)
+    }
+}
+
 pub struct SimulationPlugin;
 
 impl Plugin for SimulationPlugin {
     fn build(&self, app: &mut App) {
+        let tick_rate = *app.world.get_resource_or_insert_with(TickRate::default);
+
+        // The ground is generated from the run seed, so the `SimulationRng` has to be inserted first.
+        let seed = app.world.resource::<SimulationRng>().seed;
+        let terrain = Terrain::new(seed as u32);
+        if !app.world.contains_resource::<ObstacleLayout>() {
+            app.insert_resource(ObstacleLayout::generate(seed as u32, &terrain));
+        }
+        app.insert_resource(terrain);
+
         app.insert_resource(Wave {
-            current: 0
-        }).add_system_set(
+            current: 0,
+            cleared: Stopwatch::default()
+        }).init_resource::<SpatialGrid>()
+        .init_resource::<ObstacleGrid>()
+        .init_resource::<ArenaBounds>()
+        .init_resource::<FlowField>()
+        .init_resource::<MobArchetypes>()
+        .add_asset::<WaveTable>()
+        .init_asset_loader::<WaveTableLoader>()
+        .add_startup_system(load_wave_table)
+        .init_resource::<RunStats>()
+        .add_event::<DamageEvent>()
+        .add_event::<DamageDealtEvent>()
+        .add_event::<DeathEvent>()
+        .add_enter_system(GameState::InGame, reset_rng.run_if(run_starting))
+        .add_enter_system(GameState::InGame, reset_run_stats.run_if(run_starting))
+        // A run ends when leaving the game over screen, or when going back to the menu mid-run.
+        .add_exit_system(GameState::GameOver, end_run)
+        .add_enter_system(GameState::MainMenu, end_run)
+        // The accumulator is kept by the fixed timestep stage, which runs as many ticks as
+        // have built up since the last frame before `CoreStage::Update`.
+        .add_fixed_timestep(Duration::from_secs_f64(1.0 / tick_rate.0), SIMULATION_TIMESTEP)
+        .add_fixed_timestep_child_stage(SIMULATION_TIMESTEP)
+        .add_fixed_timestep_child_stage(SIMULATION_TIMESTEP)
+        .add_fixed_timestep_child_stage(SIMULATION_TIMESTEP)
+        .add_fixed_timestep_child_stage(SIMULATION_TIMESTEP)
+        .add_fixed_timestep_system(
+            SIMULATION_TIMESTEP,
+            STEP_PREPARE,
+            rebuild_spatial_grid.run_in_state(GameState::InGame)
+        ).add_fixed_timestep_system(
+            SIMULATION_TIMESTEP,
+            STEP_PREPARE,
+            update_obstacle_grid.run_in_state(GameState::InGame)
+        ).add_fixed_timestep_system_set(
+            SIMULATION_TIMESTEP,
+            STEP_PREPARE,
+            ConditionSet::new()
+                .run_in_state(GameState::InGame)
+                .after(PlayerLabel::Control)
+                .with_system(update_flow_field)
+                .into()
+        ).add_fixed_timestep_system_set(
+            SIMULATION_TIMESTEP,
+            STEP_FORCES,
             ConditionSet::new()
                 .run_in_state(GameState::InGame)
+                .label(SimulationLabel::InterMobForces)
                 .with_system(get_inter_mob_forces)
+                .into()
+        ).add_fixed_timestep_system_set(
+            SIMULATION_TIMESTEP,
+            STEP_FORCES,
+            ConditionSet::new()
+                .run_in_state(GameState::InGame)
+                .label(SimulationLabel::PlayerMobForces)
+                .after(SimulationLabel::InterMobForces)
                 .with_system(get_player_mob_forces)
-                .with_system(player_mob_interaction)
+                .into()
+        ).add_fixed_timestep_system_set(
+            SIMULATION_TIMESTEP,
+            STEP_FORCES,
+            ConditionSet::new()
+                .run_in_state(GameState::InGame)
+                .after(SimulationLabel::PlayerMobForces)
+                .with_system(get_obstacle_avoidance_forces)
+                .into()
+        ).add_fixed_timestep_system_set(
+            SIMULATION_TIMESTEP,
+            STEP_INTEGRATE,
+            ConditionSet::new()
+                .run_in_state(GameState::InGame)
+                .label(SimulationLabel::Mobs)
                 .with_system(simulation)
+                .into()
+        ).add_fixed_timestep_system_set(
+            SIMULATION_TIMESTEP,
+            STEP_INTEGRATE,
+            ConditionSet::new()
+                .run_in_state(GameState::InGame)
+                .label(SimulationLabel::Projectiles)
+                .after(SimulationLabel::Mobs)
                 .with_system(projectile_update)
+                .with_system(ranged_mob_attacks)
+                .with_system(mob_bolt_update)
+                .into()
+        ).add_fixed_timestep_system_set(
+            SIMULATION_TIMESTEP,
+            STEP_INTEGRATE,
+            ConditionSet::new()
+                .run_in_state(GameState::InGame)
+                .after(SimulationLabel::Projectiles)
+                .with_system(player_mob_interaction)
+                .into()
+        ).add_fixed_timestep_system(
+            SIMULATION_TIMESTEP,
+            STEP_COLLIDE,
+            resolve_collisions.run_in_state(GameState::InGame)
+        ).add_fixed_timestep_system_set(
+            SIMULATION_TIMESTEP,
+            STEP_CONFINE,
+            ConditionSet::new()
+                .run_in_state(GameState::InGame)
+                .label(SimulationLabel::Confine)
+                .with_system(confine_to_arena)
+                .into()
+        ).add_fixed_timestep_system_set(
+            SIMULATION_TIMESTEP,
+            STEP_CONFINE,
+            // Damage is dealt once everything has moved, and deaths looked at once it all has been.
+            ConditionSet::new()
+                .run_in_state(GameState::InGame)
+                .label(SimulationLabel::Damage)
+                .after(SimulationLabel::Confine)
+                .with_system(apply_damage)
+                .with_system(update_health.after(apply_damage))
+                .with_system(player_death.after(apply_damage))
+                .with_system(count_damage_dealt.after(apply_damage))
+                .with_system(write_combat_log.after(apply_damage))
                 .into()
         );
     }
@@ -34,7 +207,51 @@ impl Plugin for SimulationPlugin {
 
 #[derive(Resource)]
 pub struct Wave {
-    pub current: usize
+    pub current: usize,
+    /// Time since the last wave was cleared, used for the delay before the next one.
+    pub cleared: Stopwatch
+}
+
+/// Running totals for the current run, used by the HUD and balancing tools.
+#[derive(Resource, Default)]
+pub struct RunStats {
+    /// Simulation ticks completed this run.
+    pub ticks: u64,
+    pub elapsed: Stopwatch,
+    pub kills: usize,
+    pub damage_dealt: f32
+}
+
+fn reset_run_stats(mut stats: ResMut<RunStats>) {
+    *stats = RunStats::default();
+}
+
+/// Add up the damage the player's weapons got through.
+fn count_damage_dealt(
+    mut stats: ResMut<RunStats>,
+    mut dealt: EventReader<DamageDealtEvent>
+) {
+    for hit in dealt.iter() {
+        if let DamageKind::Weapon(_) = hit.kind {
+            stats.damage_dealt += hit.amount;
+        }
+    }
+}
+
+/// Clear away the mobs of the last run and rewind the waves, ready for a new one.
+fn end_run(
+    mut commands: Commands,
+    mut wave: ResMut<Wave>,
+    mut grid: ResMut<SpatialGrid>,
+    entities: Query<Entity, Or<(With<Mob>, With<MobBolt>)>>
+) {
+    for entity in entities.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+
+    wave.current = 0;
+    wave.cleared.reset();
+    grid.clear();
 }
 
 pub fn simulation(
@@ -42,96 +259,111 @@ pub fn simulation(
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
     mut wave: ResMut<Wave>,
-    mut mobs: Query<(Entity, &mut Transform, &mut Mob, &Handle<StandardMaterial>), With<Mob>>,
-    time: Res<Time>
+    mut rng: ResMut<SimulationRng>,
+    mut stats: ResMut<RunStats>,
+    archetypes: Res<MobArchetypes>,
+    wave_table: Res<ActiveWaveTable>,
+    wave_tables: Res<Assets<WaveTable>>,
+    mut mobs: Query<(Entity, &mut Transform, &mut Mob, &Health, &Handle<StandardMaterial>), With<Mob>>,
+    players: Query<&Transform, (With<Player>, Without<Mob>)>,
+    terrain: Res<Terrain>,
+    step: Res<FixedTimestepInfo>
 ) {
+    stats.ticks += 1;
+    stats.elapsed.tick(step.timestep());
+
     // Check if there are any mobs active in the scene, if not begin to spawn next wave.
     if mobs.is_empty() {
-        spawn_wave(&mut commands, &mut meshes, &mut materials, wave.current);
+        // The table is looked up every wave, so edits to the file apply from the next wave.
+        let table = match wave_tables.get(&wave_table.0) {
+            Some(table) => table,
+            None => return
+        };
+        wave.current = table.next_wave(wave.current);
+        let definition = table.wave(wave.current);
+
+        wave.cleared.tick(step.timestep());
+        if wave.cleared.elapsed_secs() < definition.delay {
+            return;
+        }
+
+        let player_position = players.iter().next().map_or(Vec3::ZERO, |transform| transform.translation);
+        spawn_wave(&mut commands, &mut meshes, &mut materials, &archetypes, rng.rng(), &definition, player_position);
 
         wave.current += 1;
+        wave.cleared.reset();
 
         return;
     }
 
-    let dt: f32 = time.delta_seconds();
+    let dt: f32 = step.timestep().as_secs_f32();
 
-    for (entity, mut transform, mut mob, material_handle) in mobs.iter_mut(){
-        mob.update(dt);
+    for (entity, mut transform, mut mob, health, material_handle) in mobs.iter_mut(){
+        mob.update(dt, &terrain);
 
         transform.translation = mob.pos;
         transform.scale.y = mob.strength;
 
-        if mob.health <= 0.0 {
+        if health.is_dead() {
             commands.entity(entity).despawn();
+            stats.kills += 1;
+
+            spawn_xp_orb(&mut commands, &mut meshes, &mut materials, mob.pos, mob.strength * XP_PER_STRENGTH);
+
+            if let MobBehaviour::Splitter { into, count } = &mob.behaviour {
+                for _ in 0..*count {
+                    let offset = Vec3::new(prng_offset(rng.rng()), 0.0, prng_offset(rng.rng())) * mob.size;
+                    spawn_mob(&mut commands, &mut meshes, &mut materials, &archetypes, into, mob.pos + offset, mob.strength);
+                }
+            }
         }
 
-        if mob.last_damaged.elapsed_secs() < ATTACKED_FLASH_TIME {
+        if health.since_hit() < ATTACKED_FLASH_TIME {
             let material = materials.get_mut(material_handle).unwrap();
 
-            material.base_color = color_lerp(ATTACKED_COLOR, mob.color, (mob.last_damaged.elapsed_secs()/ATTACKED_FLASH_TIME).min(1.));
+            material.base_color = color_lerp(ATTACKED_COLOR, mob.color, (health.since_hit()/ATTACKED_FLASH_TIME).min(1.));
         }
     }
 }
 
 fn player_mob_interaction(
-    mut commands: Commands,
-    mut mobs: Query<(Entity, &mut Transform, &mut Mob), With<Mob>>,
-    mut players: Query<(Entity, &mut Transform, &mut Player), (Without<Mob>, With<Player>)>,
-    time: Res<Time>
+    step: Res<FixedTimestepInfo>,
+    mut damage: EventWriter<DamageEvent>,
+    mobs: Query<(Entity, &Transform, &Mob), With<Mob>>,
+    players: Query<(Entity, &Transform, &Player), (Without<Mob>, With<Player>)>
 ) {
-    for (_, transform, mut mob) in mobs.iter_mut() {
-        for (_, p_transform, mut player) in players.iter_mut() {
-            let distance = (p_transform.translation - transform.translation).length_squared();
+    let dt = step.timestep().as_secs_f32();
 
-            if distance < 2.0_f32.powf(2.0) {
-                player.health -= mob.strength * time.delta_seconds();
+    for (entity, transform, mob) in mobs.iter() {
+        for (player_entity, p_transform, player) in players.iter() {
+            let distance = (p_transform.translation - transform.translation).length_squared();
 
-                if player.health < 0. {
-                    commands.insert_resource(NextState(GameState::GameOver));
-                }
+            // Every mob touching the player drains them, through their invulnerability window, so
+            // crowds add up. Jumping over a mob avoids it.
+            if distance < 2.0_f32.powf(2.0) && !player.clears(mob.pos.y + mob.size * 0.5) {
+                damage.send(DamageEvent {
+                    source: Some(entity),
+                    target: player_entity,
+                    amount: mob.contact_damage * dt,
+                    kind: DamageKind::Contact
+                });
             }
         }
     }
 }
 
 fn get_inter_mob_forces(
-    mut mobs: Query<(Entity, &mut Transform, &mut Mob), With<Mob>>,
+    grid: Res<SpatialGrid>,
+    mut mobs: Query<(Entity, &Transform, &mut Mob), With<Mob>>,
 ) {
-    let mut forces = Vec::new();
-
-    // Time step for current frame
-    for (entity1, transform1, mob1) in mobs.iter() {
-        let mut force = Vec3::default();
-        let mut repel = Vec3::default();
-
-        for (entity2, transform2, mob2) in mobs.iter() {
-            if entity2 != entity1 {
-                let mut distance = transform2.translation - transform1.translation;
-                let distance_squared = distance.length_squared();
-
-                if distance_squared < MAX_ATTRACTION_DISTANCE * MAX_ATTRACTION_DISTANCE {
-                    // Attraction Force
-                    force += distance;
-                }
-
-                // Repel Force
-                repel = 5.0 / distance;
-                repel.y = 0.;
-            }
-        }
-
-        force -= repel;
-
-        forces.push(force);
-    }
-
-    for (i, (_, _, mut mob)) in mobs.iter_mut().enumerate() {
-        mob.force += forces[i];
+    // Only mobs sharing a neighbouring grid cell can be within attraction distance.
+    for (entity, transform, mut mob) in mobs.iter_mut() {
+        mob.force += inter_mob_force(&grid, entity, transform.translation);
     }
 }
 
 fn get_player_mob_forces(
+    field: Res<FlowField>,
     mut mobs: Query<(Entity, &mut Transform, &mut Mob), With<Mob>>,
     mut players: Query<(Entity, &mut Transform, &mut Player), (Without<Mob>, With<Player>)>
 ) {
@@ -148,39 +380,178 @@ fn get_player_mob_forces(
             }
         }
 
-        mob.force += force;
+        // Further out, follow the flow field around obstacles rather than straight at the player.
+        if force.length_squared() > FLOW_DIRECT_DISTANCE * FLOW_DIRECT_DISTANCE {
+            if let Some(direction) = field.direction(transform.translation) {
+                force = direction * force.length();
+            }
+        }
+
+        // Ranged mobs are pushed back out once they are closer than their range.
+        if let MobBehaviour::Ranged { range, .. } = mob.behaviour {
+            force = force.normalize_or_zero() * (force.length() - range);
+        }
+
+        mob.force += force * mob.speed;
+    }
+}
+
+/// Steer mobs around obstacles, turning the forces gathered so far along their edges.
+fn get_obstacle_avoidance_forces(
+    obstacles: Res<ObstacleGrid>,
+    mut mobs: Query<&mut Mob>
+) {
+    for mut mob in mobs.iter_mut() {
+        let avoidance = obstacle_avoidance(&obstacles, mob.pos, mob.size * 0.5, mob.force);
+        mob.force += avoidance;
+    }
+}
+
+fn ranged_mob_attacks(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut mobs: Query<(Entity, &mut Mob), With<Mob>>,
+    players: Query<&Transform, (With<Player>, Without<Mob>)>,
+    step: Res<FixedTimestepInfo>
+) {
+    let player_position = match players.iter().next() {
+        Some(transform) => transform.translation,
+        None => return
+    };
+
+    for (entity, mut mob) in mobs.iter_mut() {
+        if let MobBehaviour::Ranged { range, bolt_damage, .. } = mob.behaviour {
+            mob.attack.tick(step.timestep());
+
+            let mut direction = player_position - mob.pos;
+            direction.y = 0.;
+
+            if mob.attack.just_finished() && direction.length() < range * 1.5 {
+                commands.spawn(PbrBundle {
+                    mesh: meshes.add(Mesh::from(shape::Icosphere { radius: 0.15, subdivisions: 1 })),
+                    material: materials.add(mob.color.into()),
+                    transform: Transform::from_translation(mob.pos),
+                    ..default()
+                }).insert(MobBolt {
+                    source: entity,
+                    pos: mob.pos,
+                    vel: direction.normalize_or_zero() * MOB_BOLT_SPEED,
+                    damage: bolt_damage,
+                    lifetime: Timer::new(Duration::from_secs_f32(MOB_BOLT_LIFETIME), TimerMode::Once)
+                });
+            }
+        }
+    }
+}
+
+fn mob_bolt_update(
+    mut commands: Commands,
+    terrain: Res<Terrain>,
+    obstacles: Res<ObstacleGrid>,
+    mut damage: EventWriter<DamageEvent>,
+    mut bolts: Query<(Entity, &mut Transform, &mut MobBolt), Without<Player>>,
+    players: Query<(Entity, &Transform, &Player), (With<Player>, Without<MobBolt>)>,
+    step: Res<FixedTimestepInfo>
+) {
+    for (entity, mut transform, mut bolt) in bolts.iter_mut() {
+        bolt.lifetime.tick(step.timestep());
+        bolt.update(step.timestep().as_secs_f32(), &terrain);
+        transform.translation = bolt.pos;
+
+        let mut hit = false;
+        for (player_entity, p_transform, player) in players.iter() {
+            let mut offset = p_transform.translation - bolt.pos;
+            offset.y = 0.;
+
+            if offset.length_squared() < (PLAYER_SIZE * 0.5).powi(2) && !player.clears(bolt.pos.y) {
+                damage.send(DamageEvent {
+                    source: Some(bolt.source),
+                    target: player_entity,
+                    amount: bolt.damage,
+                    kind: DamageKind::Bolt
+                });
+                hit = true;
+            }
+        }
+
+        let blocked = obstacles.near(bolt.pos, PROJECTILE_RADIUS)
+            .any(|obstacle| obstacle.overlaps(bolt.pos, PROJECTILE_RADIUS));
+
+        if hit || blocked || bolt.lifetime.finished() {
+            commands.entity(entity).despawn();
+        }
     }
 }
 
 fn projectile_update(
     mut commands: Commands,
-    time: Res<Time>,
-    mut mobs: Query<(Entity, &mut Transform, &mut Mob), With<Mob>>,
+    step: Res<FixedTimestepInfo>,
+    terrain: Res<Terrain>,
+    mut damage: EventWriter<DamageEvent>,
+    obstacles: Res<ObstacleGrid>,
+    mobs: Query<(Entity, &Transform, &Mob, &Health), With<Mob>>,
     mut proj: Query<(Entity, &mut Transform, &mut Projectile), (With<Projectile>, Without<Mob>)>,
 ) {
-    let dt = time.delta_seconds();
+    let dt = step.timestep().as_secs_f32();
 
     // Handle the projectile interaction with the mobs.
     // If projectiles need to be de-spawned after the force analysis they can be added to the hashset.
     let mut despawns = HashSet::new();
 
     for (entity, mut transform, mut projectile) in proj.iter_mut() {
-        projectile.lifetime.tick(time.delta());
+        projectile.lifetime.tick(step.timestep());
         if projectile.lifetime.finished() {
             despawns.insert(entity);
         }
 
-        let mut proj_accel = Vec3::default();
-        for (_, transform1, mut mob1) in mobs.iter_mut() {
+        for (mob_entity, transform1, _, _) in mobs.iter() {
             let distance = transform1.translation - transform.translation;
-            if distance.length_squared() < 0.5 {
-                despawns.insert(entity);
-                mob1.damage(projectile.damage);
+            let spent = projectile.hits.len() > projectile.pierce;
+            if distance.length_squared() < 0.5 && !spent && !projectile.hits.contains(&mob_entity) {
+                damage.send(DamageEvent {
+                    source: Some(projectile.source),
+                    target: mob_entity,
+                    amount: projectile.damage,
+                    kind: DamageKind::Weapon(projectile.weapon)
+                });
+
+                // Piercing projectiles carry on until they have gone through enough mobs.
+                projectile.hits.push(mob_entity);
+                if projectile.hits.len() > projectile.pierce {
+                    despawns.insert(entity);
+                }
             }
-            proj_accel += (distance)
         }
-        projectile.acc = proj_accel.normalize_or_zero();
-        projectile.update(dt);
+
+        // Locked projectiles only look for a new target once theirs is gone, the rest pick the
+        // best one every tick. Mobs already hit are left for the next target.
+        let locked = projectile.targeting == Targeting::Locked && projectile.target
+            .filter(|target| !projectile.hits.contains(target))
+            .and_then(|target| mobs.get(target).ok())
+            .map_or(false, |(_, _, _, health)| !health.is_dead());
+
+        if !locked {
+            let target = projectile.targeting.select(
+                projectile.pos,
+                mobs.iter()
+                    .filter(|(mob_entity, _, _, _)| !projectile.hits.contains(mob_entity))
+                    .map(|(mob_entity, _, mob, health)| (mob_entity, mob, health))
+            );
+            projectile.target = target;
+        }
+
+        projectile.acc = match projectile.target.and_then(|target| mobs.get(target).ok()) {
+            Some((_, mob_transform, _, _)) => (mob_transform.translation - projectile.pos).normalize_or_zero(),
+            None => Vec3::default()
+        };
+        projectile.update(dt, &terrain);
+
+        let blocked = obstacles.near(projectile.pos, PROJECTILE_RADIUS)
+            .any(|obstacle| obstacle.overlaps(projectile.pos, PROJECTILE_RADIUS));
+        if blocked {
+            despawns.insert(entity);
+        }
 
         transform.translation = projectile.pos;
     }
@@ -194,51 +565,55 @@ fn spawn_wave (
     commands: &mut Commands,
     meshes: &mut ResMut<Assets<Mesh>>,
     materials: &mut ResMut<Assets<StandardMaterial>>,
-    wave_number: usize
+    archetypes: &MobArchetypes,
+    prng: &mut impl Rng,
+    definition: &WaveDefinition,
+    player_position: Vec3
 ) {
-    // Random number generation for the simulation
-    let mut prng = thread_rng();
-
-    let mobs = get_wave(wave_number);
-
-    for _ in 0..mobs {
-        let strength = 1.0;
-        let position = Vec3::new(prng.gen::<f32>() * 20.0,
-                                 0.5,
-                                 prng.gen::<f32>() * 20.0);
-        let color = get_mob_type(strength);
-
-        commands.spawn(PbrBundle {
-            mesh: meshes.add(Mesh::from(shape::Cube { size: 1.0 })),
-            material: materials.add(color.into()),
-            transform: Transform::from_translation(position),
-            ..default()
-        }).insert(Mob {
-            pos: position,
-            vel: Vec3::default(),
-            acc: Vec3::default(),
-            force: Vec3::default(),
-            health: 10.0,
-            strength,
-            mass: 1.0,
-            color: color,
-            last_damaged: Stopwatch::default()
-        });
+    for group in definition.groups.iter() {
+        for i in 0..group.count {
+            let position = group.pattern.position(prng, i, group.count, player_position);
+
+            spawn_mob(commands, meshes, materials, archetypes, &group.mob, position, group.strength);
+        }
     }
 }
 
-pub fn get_wave(
-    wave: usize
-) -> usize {
-    let mut spawns = 0;
-
-    match wave {
-        0 => {spawns = 2},
-        1 => {spawns = 3},
-        _ => {spawns = wave.pow(2)}
-    }
+/// Spawn a single mob of archetype `id`, returning `None` if no such archetype is registered.
+pub fn spawn_mob(
+    commands: &mut Commands,
+    meshes: &mut ResMut<Assets<Mesh>>,
+    materials: &mut ResMut<Assets<StandardMaterial>>,
+    archetypes: &MobArchetypes,
+    id: &str,
+    position: Vec3,
+    strength: f32
+) -> Option<Entity> {
+    let archetype = match archetypes.get(id) {
+        Some(archetype) => archetype,
+        None => {
+            warn!("unknown mob archetype {}", id);
+            return None;
+        }
+    };
+
+    // Every mob gets its own material so it can flash when hit.
+    let entity = commands.spawn(PbrBundle {
+        mesh: meshes.add(archetype.mesh()),
+        material: materials.add(archetype.color.into()),
+        transform: Transform::from_translation(position),
+        ..default()
+    }).insert(Mob::new(id, archetype, position, strength))
+        .insert(Mob::health(archetype, strength))
+        .id();
+
+    Some(entity)
+}
 
-    return spawns;
+fn prng_offset(
+    prng: &mut impl Rng
+) -> f32 {
+    prng.gen::<f32>() - 0.5
 }
 
 pub fn color_lerp(
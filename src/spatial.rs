@@ -0,0 +1,139 @@
+use std::collections::HashMap;
+use bevy::{
+    prelude::*,
+};
+use crate::mob::Mob;
+use crate::simulation::{MAX_ATTRACTION_DISTANCE, SEPARATION_DISTANCE, SEPARATION_FORCE};
+
+/// Side length of a single grid cell. Matching the attraction distance means a mob only ever has
+/// to look at the 3x3 block of cells around it.
+pub const GRID_CELL_SIZE: f32 = MAX_ATTRACTION_DISTANCE;
+
+/// Uniform spatial hash over the ground plane (x, z). By default it holds mob positions and is
+/// rebuilt every frame.
+#[derive(Resource)]
+pub struct SpatialGrid<T = (Entity, Vec3)> {
+    cell_size: f32,
+    cells: HashMap<(i32, i32), Vec<T>>
+}
+
+impl Default for SpatialGrid {
+    fn default() -> Self {
+        SpatialGrid::new(GRID_CELL_SIZE)
+    }
+}
+
+impl<T> SpatialGrid<T> {
+    pub fn new(cell_size: f32) -> Self {
+        SpatialGrid {
+            cell_size,
+            cells: HashMap::new()
+        }
+    }
+
+    /// Empty every cell, keeping the allocations around for the next rebuild.
+    pub fn clear(&mut self) {
+        for cell in self.cells.values_mut() {
+            cell.clear();
+        }
+    }
+
+    /// Add `item` to the cell containing `position`.
+    pub fn insert_at(
+        &mut self,
+        position: Vec3,
+        item: T
+    ) {
+        let cell = self.cell(position);
+        self.cells.entry(cell).or_default().push(item);
+    }
+
+    pub fn cell(
+        &self,
+        position: Vec3
+    ) -> (i32, i32) {
+        (
+            (position.x / self.cell_size).floor() as i32,
+            (position.z / self.cell_size).floor() as i32
+        )
+    }
+
+    /// Every entry in the cells overlapping a square of half width `radius` around `position`.
+    /// Callers still need to check the actual distance.
+    pub fn neighbours(
+        &self,
+        position: Vec3,
+        radius: f32
+    ) -> impl Iterator<Item = &T> + '_ {
+        let (cx, cz) = self.cell(position);
+        let reach = (radius / self.cell_size).ceil() as i32;
+
+        (cx - reach..=cx + reach)
+            .flat_map(move |x| (cz - reach..=cz + reach).map(move |z| (x, z)))
+            .filter_map(move |cell| self.cells.get(&cell))
+            .flatten()
+    }
+}
+
+impl SpatialGrid {
+    pub fn insert(
+        &mut self,
+        entity: Entity,
+        position: Vec3
+    ) {
+        self.insert_at(position, (entity, position));
+    }
+}
+
+/// Force on a mob from another mob at `offset` from it: attraction within
+/// `MAX_ATTRACTION_DISTANCE`, and a repulsion that ramps up linearly as they close in.
+/// Overlap itself is handled by collision resolution, so the repulsion stays finite.
+pub fn pair_force(
+    mut offset: Vec3
+) -> Vec3 {
+    offset.y = 0.;
+    let distance_squared = offset.length_squared();
+
+    if distance_squared >= MAX_ATTRACTION_DISTANCE * MAX_ATTRACTION_DISTANCE {
+        return Vec3::default();
+    }
+
+    // Attraction Force
+    let mut force = offset;
+
+    // Repel Force
+    let distance = distance_squared.sqrt();
+    if distance < SEPARATION_DISTANCE {
+        force -= offset.normalize_or_zero() * SEPARATION_FORCE * (1.0 - distance / SEPARATION_DISTANCE);
+    }
+
+    force
+}
+
+/// Attraction and repulsion acting on a single mob from the mobs around it.
+pub fn inter_mob_force(
+    grid: &SpatialGrid,
+    entity: Entity,
+    position: Vec3
+) -> Vec3 {
+    let mut force = Vec3::default();
+
+    for (other, other_position) in grid.neighbours(position, MAX_ATTRACTION_DISTANCE) {
+        if *other != entity {
+            force += pair_force(*other_position - position);
+        }
+    }
+
+    force
+}
+
+pub fn rebuild_spatial_grid(
+    mut grid: ResMut<SpatialGrid>,
+    mobs: Query<(Entity, &Transform), With<Mob>>
+) {
+    grid.clear();
+
+    for (entity, transform) in mobs.iter() {
+        grid.insert(entity, transform.translation);
+    }
+}
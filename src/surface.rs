@@ -3,14 +3,13 @@ use bevy::{
     prelude::*,
 };
 use bevy::render::camera::ScalingMode;
+use crate::camera::{DEFAULT_ZOOM, GameCamera};
+use crate::obstacle::{ObstacleLayout, spawn_obstacles};
+use crate::terrain::{GROUND_HALF_EXTENT, GROUND_SIZE, Terrain};
 use bevy_mod_raycast::{DefaultPluginState, DefaultRaycastingPlugin, RaycastMesh, RaycastMethod, RaycastSource, RaycastSystem};
 
-pub const GROUND_COLOR: f32 = 0.1;
-pub const GROUND_SIZE: f32 = 16.0;
 pub const CAMERA_DISTANCE: f32 = GROUND_SIZE * 0.8;
 
-pub const GROUND_PLANES: i32 = 3;
-
 pub struct SurfacePlugin;
 
 impl Plugin for SurfacePlugin {
@@ -34,9 +33,6 @@ impl Plugin for SurfacePlugin {
     }
 }
 
-#[derive(Component)]
-pub struct GameCamera;
-
 /// This is a unit struct we will use to mark our generic `RaycastMesh`s and `RaycastSource` as part
 /// of the same group, or "RaycastSet". For more complex use cases, you might use this to associate
 /// some meshes with one ray casting source, and other meshes with a different ray casting source."
@@ -99,32 +95,24 @@ pub fn generate_world(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
+    terrain: Res<Terrain>,
+    layout: Res<ObstacleLayout>,
 ) {
     commands.insert_resource(DefaultPluginState::<Surface>::default());
 
-    for plane_x in (-GROUND_PLANES+1)..GROUND_PLANES {
-        for plane_y in (-GROUND_PLANES+1)..GROUND_PLANES {
-            // Ground
-            commands.spawn(PbrBundle {
-                mesh: meshes.add(Mesh::from(shape::Plane { size: GROUND_SIZE })),
-                material: materials.add(
-                    Color::rgb(
-                        GROUND_COLOR + (plane_x.abs() as f32 * 0.1),
-                        GROUND_COLOR,
-                        GROUND_COLOR
-                    ).into()),
-                transform: Transform {
-                    translation: Vec3::new(
-                        plane_x as f32 * GROUND_SIZE ,
-                        0f32,
-                        plane_y as f32 * GROUND_SIZE),
-                    scale: Vec3::new(1.0, 1.0, 1.0),
-                    ..default()
-                },
-                ..default()
-            }).insert(RaycastMesh::<Surface>::default()); // Make this mesh ray cast-able
-        }
-    }
+    // Ground, one heightmap mesh covering the whole grid of planes
+    commands.spawn(PbrBundle {
+        mesh: meshes.add(terrain.mesh(GROUND_HALF_EXTENT)),
+        // The height colouring is in the vertex colours, which the base colour multiplies
+        material: materials.add(StandardMaterial {
+            base_color: Color::WHITE,
+            perceptual_roughness: 0.9,
+            ..default()
+        }),
+        ..default()
+    }).insert(RaycastMesh::<Surface>::default()); // Make this mesh ray cast-able
+
+    spawn_obstacles(&mut commands, &mut meshes, &mut materials, &layout, &terrain);
 
     commands.spawn(Camera3dBundle {
         camera: Camera {
@@ -132,7 +120,7 @@ pub fn generate_world(
             ..default()
         },
         projection: OrthographicProjection {
-            scale: 3.0,
+            scale: DEFAULT_ZOOM,
             scaling_mode: ScalingMode::FixedVertical(CAMERA_DISTANCE),
             ..default()
         }.into(),
@@ -144,6 +132,6 @@ pub fn generate_world(
             .looking_at(Vec3::ZERO, Vec3::Y),
         ..Default::default()
         })
-            .insert(GameCamera {})
+            .insert(GameCamera::new(CAMERA_DISTANCE))
             .insert(RaycastSource::<Surface>::new()); // Designate the camera as our source
 }
\ No newline at end of file
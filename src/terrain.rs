@@ -0,0 +1,119 @@
+use bevy::{
+    prelude::*,
+};
+use bevy::render::mesh::{Indices, PrimitiveTopology};
+use noise::{Fbm, MultiFractal, NoiseFn, Perlin};
+
+pub const GROUND_SIZE: f32 = 16.0;
+pub const GROUND_PLANES: i32 = 3;
+
+/// Distance from the centre to the edge of the ground, which spans `2 * GROUND_PLANES - 1` planes.
+pub const GROUND_HALF_EXTENT: f32 = GROUND_SIZE * (GROUND_PLANES as f32 - 0.5);
+
+/// Height of the tallest hills above the lowest valleys.
+pub const TERRAIN_AMPLITUDE: f32 = 3.0;
+/// Noise frequency per world unit, lower values give broader hills.
+pub const TERRAIN_FREQUENCY: f64 = 0.04;
+pub const TERRAIN_OCTAVES: usize = 4;
+/// Mesh vertices per world unit.
+pub const TERRAIN_RESOLUTION: f32 = 1.0;
+
+pub const TERRAIN_LOW_COLOR: Color = Color::rgb(0.08, 0.08, 0.1);
+pub const TERRAIN_HIGH_COLOR: Color = Color::rgb(0.3, 0.12, 0.12);
+
+/// Heightmap of the ground, generated from the run seed.
+///
+/// Everything that moves on the ground takes its height from here, so the simulation has the
+/// same hills whether or not there is a mesh to look at.
+#[derive(Resource)]
+pub struct Terrain {
+    noise: Fbm<Perlin>,
+    pub amplitude: f32
+}
+
+impl Terrain {
+    pub fn new(seed: u32) -> Self {
+        Terrain {
+            noise: Fbm::<Perlin>::new(seed)
+                .set_octaves(TERRAIN_OCTAVES)
+                .set_frequency(TERRAIN_FREQUENCY),
+            amplitude: TERRAIN_AMPLITUDE
+        }
+    }
+
+    /// Ground height at a point on the plane, between 0 and `amplitude`.
+    pub fn height(
+        &self,
+        x: f32,
+        z: f32
+    ) -> f32 {
+        let value = self.noise.get([x as f64, z as f64]) as f32;
+
+        (value.clamp(-1.0, 1.0) * 0.5 + 0.5) * self.amplitude
+    }
+
+    pub fn normal(
+        &self,
+        x: f32,
+        z: f32
+    ) -> Vec3 {
+        let e = 0.5 / TERRAIN_RESOLUTION;
+        let dx = self.height(x + e, z) - self.height(x - e, z);
+        let dz = self.height(x, z + e) - self.height(x, z - e);
+
+        Vec3::new(-dx, 2.0 * e, -dz).normalize()
+    }
+
+    /// Square mesh of the ground centred on the origin, coloured by height.
+    pub fn mesh(
+        &self,
+        half_size: f32
+    ) -> Mesh {
+        let cells = (2.0 * half_size * TERRAIN_RESOLUTION).ceil() as u32;
+        let spacing = 2.0 * half_size / cells as f32;
+
+        let mut positions = Vec::new();
+        let mut normals = Vec::new();
+        let mut uvs = Vec::new();
+        let mut colors = Vec::new();
+
+        for j in 0..=cells {
+            for i in 0..=cells {
+                let x = -half_size + i as f32 * spacing;
+                let z = -half_size + j as f32 * spacing;
+                let height = self.height(x, z);
+                let t = height / self.amplitude;
+
+                positions.push([x, height, z]);
+                normals.push(self.normal(x, z).to_array());
+                uvs.push([i as f32 / cells as f32, j as f32 / cells as f32]);
+                colors.push([
+                    TERRAIN_LOW_COLOR.r() + (TERRAIN_HIGH_COLOR.r() - TERRAIN_LOW_COLOR.r()) * t,
+                    TERRAIN_LOW_COLOR.g() + (TERRAIN_HIGH_COLOR.g() - TERRAIN_LOW_COLOR.g()) * t,
+                    TERRAIN_LOW_COLOR.b() + (TERRAIN_HIGH_COLOR.b() - TERRAIN_LOW_COLOR.b()) * t,
+                    1.0
+                ]);
+            }
+        }
+
+        // Two triangles per cell, wound counter clockwise seen from above.
+        let mut indices = Vec::new();
+        let row = cells + 1;
+        for j in 0..cells {
+            for i in 0..cells {
+                let corner = j * row + i;
+                indices.extend_from_slice(&[corner, corner + row, corner + 1]);
+                indices.extend_from_slice(&[corner + 1, corner + row, corner + row + 1]);
+            }
+        }
+
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, colors);
+        mesh.set_indices(Some(Indices::U32(indices)));
+
+        mesh
+    }
+}
@@ -4,21 +4,38 @@ use bevy::{
 use bevy::app::AppExit;
 use bevy::window::close_on_esc;
 use iyes_loopless::prelude::*;
-use crate::GameState;
+use crate::{GameState, run_starting};
 use crate::mob::Mob;
+use crate::health::Health;
+use crate::input::{Action, ActionState, Binding};
 use crate::player::Player;
-use crate::simulation::Wave;
+use crate::highscore::{HighScore, HighScores};
+use crate::progression::{choose_upgrade, Experience, PendingUpgrades};
+use crate::rng::SimulationRng;
+use crate::settings::Settings;
+use crate::simulation::{RunStats, Wave};
 
 pub struct UIPlugin;
 impl Plugin for UIPlugin {
     fn build(&self, app: &mut App) {
-        app   // menu setup (state enter) systems
+        app.insert_resource(HighScores::load())
+            .init_resource::<SettingsReturn>()
+            .init_resource::<Rebinding>()
+            // menu setup (state enter) systems
             .add_enter_system(GameState::MainMenu, setup_menu)
-            .add_enter_system(GameState::InGame, game_ui)
+            .add_enter_system(GameState::InGame, game_ui.run_if(run_starting))
+            .add_enter_system(GameState::Paused, pause_menu)
+            .add_enter_system(GameState::LevelUp, level_up_menu)
+            .add_enter_system(GameState::Settings, settings_menu)
             .add_enter_system(GameState::GameOver, game_over_ui)
             // menu cleanup (state exit) systems
             .add_exit_system(GameState::MainMenu, despawn_with::<MainMenu>)
-            .add_exit_system(GameState::InGame, despawn_with::<GameUI>)
+            // the HUD lasts the whole run, through pauses and level ups
+            .add_enter_system(GameState::GameOver, despawn_with::<GameUI>)
+            .add_enter_system(GameState::MainMenu, despawn_with::<GameUI>)
+            .add_exit_system(GameState::Paused, despawn_with::<PauseMenu>)
+            .add_exit_system(GameState::LevelUp, despawn_with::<LevelUpMenu>)
+            .add_exit_system(GameState::Settings, despawn_with::<SettingsMenu>)
             .add_exit_system(GameState::GameOver, despawn_with::<GameOverText>)
             // menu stuff
             .add_system_set(
@@ -29,12 +46,49 @@ impl Plugin for UIPlugin {
                     // our menu button handlers
                     .with_system(butt_exit.run_if(on_butt_interact::<ExitButt>))
                     .with_system(butt_game.run_if(on_butt_interact::<EnterButt>))
+                    .with_system(butt_settings.run_if(on_butt_interact::<SettingsButt>))
+                    .into()
+            )// game over stuff
+            .add_system_set(
+                ConditionSet::new()
+                    .run_in_state(GameState::GameOver)
+                    .with_system(butt_interact_visual)
+                    .with_system(butt_game.run_if(on_butt_interact::<RetryButt>))
+                    .with_system(butt_menu.run_if(on_butt_interact::<MenuButt>))
+                    .into()
+            )// pause menu stuff
+            .add_system_set(
+                ConditionSet::new()
+                    .run_in_state(GameState::Paused)
+                    .with_system(resume_on_esc)
+                    .with_system(butt_interact_visual)
+                    .with_system(butt_resume.run_if(on_butt_interact::<ResumeButt>))
+                    .with_system(butt_settings.run_if(on_butt_interact::<SettingsButt>))
+                    .with_system(butt_menu.run_if(on_butt_interact::<QuitButt>))
+                    .into()
+            )// settings stuff
+            .add_system_set(
+                ConditionSet::new()
+                    .run_in_state(GameState::Settings)
+                    .with_system(butt_interact_visual)
+                    .with_system(butt_setting)
+                    .with_system(capture_rebind)
+                    .with_system(update_setting_text)
+                    .with_system(back_on_esc)
+                    .with_system(butt_back.run_if(on_butt_interact::<BackButt>))
+                    .into()
+            )// level up stuff
+            .add_system_set(
+                ConditionSet::new()
+                    .run_in_state(GameState::LevelUp)
+                    .with_system(butt_interact_visual)
+                    .with_system(butt_upgrade)
                     .into()
             )// in-game stuff
             .add_system_set(
                 ConditionSet::new()
                     .run_in_state(GameState::InGame)
-                    .with_system(back_to_menu_on_esc)
+                    .with_system(pause_on_esc)
                     .with_system(update_ui)
                     .into()
             );
@@ -65,9 +119,82 @@ struct EnterButt;
 #[derive(Component)]
 struct GameOverText;
 
+/// Marker for the pause overlay
+#[derive(Component)]
+struct PauseMenu;
+
+/// Marker for the "Resume" button
+#[derive(Component)]
+struct ResumeButt;
+
+/// Marker for the "Settings" button
+#[derive(Component)]
+struct SettingsButt;
+
+/// Marker for the settings screen
+#[derive(Component)]
+struct SettingsMenu;
+
+/// Something that can be changed on the settings screen
+#[derive(Clone, Copy, PartialEq)]
+enum SettingField {
+    VSync,
+    Msaa,
+    CameraDistance,
+    Volume,
+    Binding(Action),
+}
+
+/// Button changing a setting, or starting to rebind an action
+#[derive(Component)]
+struct SettingButt(SettingField);
+
+/// Label of a `SettingButt`, showing the current value
+#[derive(Component)]
+struct SettingText(SettingField);
+
+/// Marker for the "Back" button on the settings screen
+#[derive(Component)]
+struct BackButt;
+
+/// The menu the settings screen was opened from, to go back to.
+#[derive(Resource)]
+struct SettingsReturn(GameState);
+
+impl Default for SettingsReturn {
+    fn default() -> Self {
+        SettingsReturn(GameState::MainMenu)
+    }
+}
+
+/// Action waiting for the next key or button press to be bound to it.
+#[derive(Resource, Default)]
+struct Rebinding(Option<Action>);
+
+/// Marker for the "Quit to Menu" button
+#[derive(Component)]
+struct QuitButt;
+
+/// Marker for the level up overlay
+#[derive(Component)]
+struct LevelUpMenu;
+
+/// Button picking one of the upgrades on offer, by its index in the offer
+#[derive(Component)]
+struct UpgradeButt(usize);
+
+/// Marker for the "Retry" button
+#[derive(Component)]
+struct RetryButt;
+
+/// Marker for the "Main Menu" button
+#[derive(Component)]
+struct MenuButt;
+
 fn setup_menu(
     mut commands: Commands,
-    asset_server: Res<AssetServer>
+    asset_server: Res<AssetServer>,
+    high_scores: Res<HighScores>
 ) {
     let butt_style = Style {
         justify_content: JustifyContent::Center,
@@ -111,6 +238,19 @@ fn setup_menu(
         })
         .id();
 
+    let butt_settings = commands
+        .spawn((ButtonBundle {
+            style: butt_style.clone(),
+            ..Default::default()
+        }, SettingsButt))
+        .with_children(|btn| {
+            btn.spawn(TextBundle {
+                text: Text::from_section("Settings", butt_textstyle.clone()),
+                ..Default::default()
+            });
+        })
+        .id();
+
     let butt_exit = commands
         .spawn((ButtonBundle {
             style: butt_style.clone(),
@@ -124,14 +264,41 @@ fn setup_menu(
         })
         .id();
 
+    let mut scores = String::from("High Scores");
+    if high_scores.entries.is_empty() {
+        scores.push_str("\nnone yet");
+    }
+    for (i, score) in high_scores.entries.iter().enumerate() {
+        scores.push_str(&format!(
+            "\n{}. wave {}  {:.0}s  {} kills  seed {}",
+            i + 1, score.waves, score.time, score.kills, score.seed
+        ));
+    }
+
+    let score_table = commands
+        .spawn(TextBundle {
+            text: Text::from_section(scores, TextStyle {
+                font: asset_server.load("fonts/framdit.ttf"),
+                font_size: 18.0,
+                color: Color::BLACK,
+            }),
+            style: Style {
+                margin: UiRect::all(Val::Px(8.0)),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .id();
+
     commands
         .entity(menu)
-        .push_children(&[butt_enter, butt_exit]);
+        .push_children(&[butt_enter, butt_settings, butt_exit, score_table]);
 }
 
 fn game_ui(
     mut commands: Commands,
-    asset_server: Res<AssetServer>
+    asset_server: Res<AssetServer>,
+    rng: Res<SimulationRng>
 ) {
     // Text with one section
     commands.spawn((
@@ -154,6 +321,14 @@ fn game_ui(
                     font_size: 30.0,
                     color: Color::WHITE,
                 },
+            ),
+            TextSection::new(
+                format!("\nseed {}", rng.seed),
+                TextStyle {
+                    font: asset_server.load("fonts/framdit.ttf"),
+                    font_size: 20.0,
+                    color: Color::WHITE,
+                },
             )
         ]),
         UIText,
@@ -163,58 +338,464 @@ fn game_ui(
 
 fn game_over_ui(
     mut commands: Commands,
-    asset_server: Res<AssetServer>
+    asset_server: Res<AssetServer>,
+    rng: Res<SimulationRng>,
+    wave: Res<Wave>,
+    stats: Res<RunStats>,
+    mut high_scores: ResMut<HighScores>
 ) {
-    commands.spawn((
-        // Create a TextBundle that has a Text with a single section.
-        TextBundle::from_section(
-            // Accepts a `String` or any type that converts into a `String`, such as `&str`
+    let rank = high_scores.insert(HighScore {
+        waves: wave.current,
+        time: stats.elapsed.elapsed_secs(),
+        kills: stats.kills,
+        seed: rng.seed
+    });
+    high_scores.save();
+
+    let mut summary = format!(
+        "wave {}\n{:.0}s survived\n{} kills\nseed {}",
+        wave.current, stats.elapsed.elapsed_secs(), stats.kills, rng.seed
+    );
+    if let Some(rank) = rank {
+        summary.push_str(&format!("\n#{} high score", rank + 1));
+    }
+
+    let butt_style = Style {
+        justify_content: JustifyContent::Center,
+        align_items: AlignItems::Center,
+        padding: UiRect::all(Val::Px(8.0)),
+        margin: UiRect::all(Val::Px(4.0)),
+        flex_grow: 1.0,
+        ..Default::default()
+    };
+    let butt_textstyle = TextStyle {
+        font: asset_server.load("fonts/framdit.ttf"),
+        font_size: 24.0,
+        color: Color::BLACK,
+    };
+
+    let menu = commands
+        .spawn((NodeBundle {
+            background_color: BackgroundColor(Color::rgba(0.1, 0.1, 0.1, 0.8)),
+            style: Style {
+                size: Size::new(Val::Auto, Val::Auto),
+                margin: UiRect::all(Val::Auto),
+                padding: UiRect::all(Val::Px(16.0)),
+                align_self: AlignSelf::Center,
+                align_items: AlignItems::Center,
+                flex_direction: FlexDirection::Column,
+                justify_content: JustifyContent::Center,
+                ..Default::default()
+            },
+            ..Default::default()
+        }, GameOverText))
+        .id();
+
+    let title = commands
+        .spawn(TextBundle::from_section(
             "GameOver!",
             TextStyle {
                 font: asset_server.load("fonts/framdit.ttf"),
                 font_size: 100.0,
                 color: Color::WHITE,
             },
-        ) // Set the alignment of the Text
-            .with_text_alignment(TextAlignment::TOP_CENTER)
-            // Set the style of the TextBundle itself.
-            .with_style(Style {
+        ))
+        .id();
+
+    let details = commands
+        .spawn(TextBundle::from_section(
+            summary,
+            TextStyle {
+                font: asset_server.load("fonts/framdit.ttf"),
+                font_size: 30.0,
+                color: Color::WHITE,
+            },
+        ).with_text_alignment(TextAlignment::TOP_CENTER))
+        .id();
+
+    let butt_retry = commands
+        .spawn((ButtonBundle {
+            style: butt_style.clone(),
+            ..Default::default()
+        }, RetryButt))
+        .with_children(|btn| {
+            btn.spawn(TextBundle {
+                text: Text::from_section("Retry", butt_textstyle.clone()),
+                ..Default::default()
+            });
+        })
+        .id();
+
+    let butt_menu = commands
+        .spawn((ButtonBundle {
+            style: butt_style.clone(),
+            ..Default::default()
+        }, MenuButt))
+        .with_children(|btn| {
+            btn.spawn(TextBundle {
+                text: Text::from_section("Main Menu", butt_textstyle.clone()),
+                ..Default::default()
+            });
+        })
+        .id();
+
+    commands
+        .entity(menu)
+        .push_children(&[title, details, butt_retry, butt_menu]);
+}
+
+fn pause_menu(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>
+) {
+    let butt_style = Style {
+        justify_content: JustifyContent::Center,
+        align_items: AlignItems::Center,
+        padding: UiRect::all(Val::Px(8.0)),
+        margin: UiRect::all(Val::Px(4.0)),
+        flex_grow: 1.0,
+        ..Default::default()
+    };
+    let butt_textstyle = TextStyle {
+        font: asset_server.load("fonts/framdit.ttf"),
+        font_size: 24.0,
+        color: Color::BLACK,
+    };
+
+    // Full screen backdrop dimming the frozen game behind the menu.
+    let overlay = commands
+        .spawn((NodeBundle {
+            background_color: BackgroundColor(Color::rgba(0.0, 0.0, 0.0, 0.5)),
+            style: Style {
+                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
                 position_type: PositionType::Absolute,
-                position: UiRect {
-                    bottom: Val::Px(5.0),
-                    right: Val::Px(15.0),
-                    ..default()
-                },
-                ..default()
-            }),
-        GameOverText,
-    ));
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+            ..Default::default()
+        }, PauseMenu))
+        .id();
+
+    let menu = commands
+        .spawn(NodeBundle {
+            background_color: BackgroundColor(Color::rgb(0.5, 0.5, 0.5)),
+            style: Style {
+                size: Size::new(Val::Auto, Val::Auto),
+                flex_direction: FlexDirection::Column,
+                justify_content: JustifyContent::Center,
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .id();
+
+    let butt_resume = commands
+        .spawn((ButtonBundle {
+            style: butt_style.clone(),
+            ..Default::default()
+        }, ResumeButt))
+        .with_children(|btn| {
+            btn.spawn(TextBundle {
+                text: Text::from_section("Resume", butt_textstyle.clone()),
+                ..Default::default()
+            });
+        })
+        .id();
+
+    let butt_settings = commands
+        .spawn((ButtonBundle {
+            style: butt_style.clone(),
+            ..Default::default()
+        }, SettingsButt))
+        .with_children(|btn| {
+            btn.spawn(TextBundle {
+                text: Text::from_section("Settings", butt_textstyle.clone()),
+                ..Default::default()
+            });
+        })
+        .id();
+
+    let butt_quit = commands
+        .spawn((ButtonBundle {
+            style: butt_style.clone(),
+            ..Default::default()
+        }, QuitButt))
+        .with_children(|btn| {
+            btn.spawn(TextBundle {
+                text: Text::from_section("Quit to Menu", butt_textstyle.clone()),
+                ..Default::default()
+            });
+        })
+        .id();
+
+    commands
+        .entity(menu)
+        .push_children(&[butt_resume, butt_settings, butt_quit]);
+    commands
+        .entity(overlay)
+        .push_children(&[menu]);
+}
+
+fn level_up_menu(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    experience: Res<Experience>
+) {
+    let butt_style = Style {
+        justify_content: JustifyContent::Center,
+        align_items: AlignItems::Center,
+        padding: UiRect::all(Val::Px(8.0)),
+        margin: UiRect::all(Val::Px(4.0)),
+        flex_grow: 1.0,
+        ..Default::default()
+    };
+    let butt_textstyle = TextStyle {
+        font: asset_server.load("fonts/framdit.ttf"),
+        font_size: 24.0,
+        color: Color::BLACK,
+    };
+
+    // Full screen backdrop, like the pause menu.
+    let overlay = commands
+        .spawn((NodeBundle {
+            background_color: BackgroundColor(Color::rgba(0.0, 0.0, 0.0, 0.5)),
+            style: Style {
+                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                position_type: PositionType::Absolute,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+            ..Default::default()
+        }, LevelUpMenu))
+        .id();
+
+    let menu = commands
+        .spawn(NodeBundle {
+            background_color: BackgroundColor(Color::rgb(0.5, 0.5, 0.5)),
+            style: Style {
+                size: Size::new(Val::Auto, Val::Auto),
+                padding: UiRect::all(Val::Px(8.0)),
+                align_items: AlignItems::Center,
+                flex_direction: FlexDirection::Column,
+                justify_content: JustifyContent::Center,
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .id();
+
+    let title = commands
+        .spawn(TextBundle::from_section(
+            format!("Level {}", experience.level),
+            TextStyle {
+                font: asset_server.load("fonts/framdit.ttf"),
+                font_size: 40.0,
+                color: Color::BLACK,
+            },
+        ))
+        .id();
+    commands.entity(menu).push_children(&[title]);
+
+    // One button for each upgrade on offer.
+    for (i, upgrade) in experience.offer.iter().enumerate() {
+        let butt_upgrade = commands
+            .spawn((ButtonBundle {
+                style: butt_style.clone(),
+                ..Default::default()
+            }, UpgradeButt(i)))
+            .with_children(|btn| {
+                btn.spawn(TextBundle {
+                    text: Text::from_section(upgrade.describe(), butt_textstyle.clone()),
+                    ..Default::default()
+                });
+            })
+            .id();
+
+        commands.entity(menu).push_children(&[butt_upgrade]);
+    }
+
+    commands
+        .entity(overlay)
+        .push_children(&[menu]);
+}
+
+fn settings_menu(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    settings: Res<Settings>,
+    mut rebinding: ResMut<Rebinding>
+) {
+    rebinding.0 = None;
+
+    let butt_style = Style {
+        justify_content: JustifyContent::Center,
+        align_items: AlignItems::Center,
+        padding: UiRect::all(Val::Px(4.0)),
+        margin: UiRect::all(Val::Px(2.0)),
+        ..Default::default()
+    };
+    let butt_textstyle = TextStyle {
+        font: asset_server.load("fonts/framdit.ttf"),
+        font_size: 20.0,
+        color: Color::BLACK,
+    };
+    let column_style = Style {
+        size: Size::new(Val::Auto, Val::Auto),
+        margin: UiRect::all(Val::Px(8.0)),
+        flex_direction: FlexDirection::Column,
+        justify_content: JustifyContent::FlexStart,
+        ..Default::default()
+    };
+
+    // Full screen backdrop, like the pause menu.
+    let overlay = commands
+        .spawn((NodeBundle {
+            background_color: BackgroundColor(Color::rgba(0.0, 0.0, 0.0, 0.5)),
+            style: Style {
+                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                position_type: PositionType::Absolute,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+            ..Default::default()
+        }, SettingsMenu))
+        .id();
+
+    let menu = commands
+        .spawn(NodeBundle {
+            background_color: BackgroundColor(Color::rgb(0.5, 0.5, 0.5)),
+            style: Style {
+                size: Size::new(Val::Auto, Val::Auto),
+                padding: UiRect::all(Val::Px(8.0)),
+                align_items: AlignItems::Center,
+                flex_direction: FlexDirection::Column,
+                justify_content: JustifyContent::Center,
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .id();
+
+    let columns = commands
+        .spawn(NodeBundle {
+            background_color: BackgroundColor(Color::NONE),
+            style: Style {
+                size: Size::new(Val::Auto, Val::Auto),
+                flex_direction: FlexDirection::Row,
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .id();
+
+    // Display settings on the left, key bindings on the right.
+    let display = [SettingField::VSync, SettingField::Msaa, SettingField::CameraDistance, SettingField::Volume];
+    let controls = Action::ALL.map(SettingField::Binding);
+
+    for fields in [&display[..], &controls[..]] {
+        let column = commands
+            .spawn(NodeBundle {
+                background_color: BackgroundColor(Color::NONE),
+                style: column_style.clone(),
+                ..Default::default()
+            })
+            .id();
+
+        for field in fields {
+            let butt_setting = commands
+                .spawn((ButtonBundle {
+                    style: butt_style.clone(),
+                    ..Default::default()
+                }, SettingButt(*field)))
+                .with_children(|btn| {
+                    btn.spawn((TextBundle {
+                        text: Text::from_section(describe_setting(*field, &settings, None), butt_textstyle.clone()),
+                        ..Default::default()
+                    }, SettingText(*field)));
+                })
+                .id();
+
+            commands.entity(column).push_children(&[butt_setting]);
+        }
+
+        commands.entity(columns).push_children(&[column]);
+    }
+
+    let butt_back = commands
+        .spawn((ButtonBundle {
+            style: butt_style.clone(),
+            ..Default::default()
+        }, BackButt))
+        .with_children(|btn| {
+            btn.spawn(TextBundle {
+                text: Text::from_section("Back", butt_textstyle.clone()),
+                ..Default::default()
+            });
+        })
+        .id();
+
+    commands
+        .entity(menu)
+        .push_children(&[columns, butt_back]);
+    commands
+        .entity(overlay)
+        .push_children(&[menu]);
+}
+
+/// Label of a setting button, e.g. `VSync: On` or `Jump: Space`.
+fn describe_setting(
+    field: SettingField,
+    settings: &Settings,
+    rebinding: Option<Action>
+) -> String {
+    match field {
+        SettingField::VSync => format!("VSync: {}", if settings.vsync { "On" } else { "Off" }),
+        SettingField::Msaa => format!("MSAA: {}x", settings.msaa),
+        SettingField::CameraDistance => format!("Camera Distance: {:.0}", settings.camera_distance),
+        SettingField::Volume => format!("Volume: {:.0}%", settings.volume * 100.0),
+        SettingField::Binding(action) if rebinding == Some(action) => format!("{:?}: press a key", action),
+        SettingField::Binding(action) => {
+            let bindings: Vec<String> = settings.bindings.get(action).iter().map(Binding::describe).collect();
+            format!("{:?}: {}", action, bindings.join(", "))
+        }
+    }
 }
 
 fn update_ui(
-    mut players: Query<(&mut Player), (Without<Mob>, With<Player>)>,
+    players: Query<&Health, (Without<Mob>, With<Player>)>,
     mut text: Query<(&mut Text), With<UIText>>,
-    wave: Res<Wave>
+    wave: Res<Wave>,
+    experience: Res<Experience>
 ) {
     for (mut text) in &mut text {
-        for (mut player) in players.iter_mut() {
+        for health in players.iter() {
             text.sections[0].value = format!(
-                "{:.1} health",
-                player.health
+                "{:.1} / {:.0} health",
+                health.current, health.max
             );
 
             text.sections[1].value = format!(
-                "\nwave {:.1}",
-                wave.current
+                "\nwave {:.1}\nlevel {} ({:.0}/{:.0} xp)",
+                wave.current, experience.level, experience.xp, experience.next_level()
             );
         }
     }
 }
 
-/// Transition back to menu on pressing Escape
-fn back_to_menu_on_esc(mut commands: Commands, kbd: Res<Input<KeyCode>>) {
-    if kbd.just_pressed(KeyCode::Escape) {
-        commands.insert_resource(NextState(GameState::MainMenu));
+/// Pause the run on pressing Escape, or whatever pause is bound to
+fn pause_on_esc(mut commands: Commands, actions: Res<ActionState>) {
+    if actions.just_pressed(Action::Pause) {
+        commands.insert_resource(NextState(GameState::Paused));
+    }
+}
+
+/// Resume the run on pressing pause again
+fn resume_on_esc(commands: Commands, actions: Res<ActionState>) {
+    if actions.just_pressed(Action::Pause) {
+        butt_resume(commands);
     }
 }
 
@@ -270,3 +851,125 @@ fn butt_game(mut commands: Commands) {
     commands.insert_resource(NextState(GameState::InGame));
 }
 
+/// Handler for the Main Menu button
+fn butt_menu(mut commands: Commands) {
+    commands.insert_resource(NextState(GameState::MainMenu));
+}
+
+/// Handler for the Resume button
+fn butt_resume(mut commands: Commands) {
+    commands.insert_resource(NextState(GameState::InGame));
+}
+
+/// Handler for the upgrade buttons on the level up screen
+fn butt_upgrade(
+    mut commands: Commands,
+    query: Query<(&Interaction, &UpgradeButt), (Changed<Interaction>, With<Button>)>,
+    experience: Res<Experience>,
+    mut pending: ResMut<PendingUpgrades>
+) {
+    for (interaction, butt) in query.iter() {
+        if *interaction == Interaction::Clicked {
+            choose_upgrade(&mut commands, &experience, &mut pending, butt.0);
+            return;
+        }
+    }
+}
+
+/// Handler for the Settings buttons on the main and pause menus
+fn butt_settings(
+    mut commands: Commands,
+    state: Res<CurrentState<GameState>>
+) {
+    commands.insert_resource(SettingsReturn(state.0));
+    commands.insert_resource(NextState(GameState::Settings));
+}
+
+/// Handler for the Back button on the settings screen
+fn butt_back(
+    mut commands: Commands,
+    from: Res<SettingsReturn>
+) {
+    commands.insert_resource(NextState(from.0));
+}
+
+/// Go back from the settings screen on pressing pause, unless it is being rebound
+fn back_on_esc(
+    commands: Commands,
+    actions: Res<ActionState>,
+    rebinding: Res<Rebinding>,
+    from: Res<SettingsReturn>
+) {
+    if actions.just_pressed(Action::Pause) && rebinding.0.is_none() && !rebinding.is_changed() {
+        butt_back(commands, from);
+    }
+}
+
+/// Handler for the setting buttons, saving every change straight away
+fn butt_setting(
+    query: Query<(&Interaction, &SettingButt), (Changed<Interaction>, With<Button>)>,
+    mut settings: ResMut<Settings>,
+    mut rebinding: ResMut<Rebinding>
+) {
+    for (interaction, butt) in query.iter() {
+        if *interaction != Interaction::Clicked {
+            continue;
+        }
+
+        match butt.0 {
+            SettingField::VSync => settings.vsync = !settings.vsync,
+            SettingField::Msaa => settings.cycle_msaa(),
+            SettingField::CameraDistance => settings.step_camera_distance(),
+            SettingField::Volume => settings.step_volume(),
+            SettingField::Binding(action) => {
+                rebinding.0 = Some(action);
+                continue;
+            }
+        }
+
+        settings.save();
+    }
+}
+
+/// Bind the action waiting to be rebound to the next key, mouse button or gamepad button pressed
+fn capture_rebind(
+    keys: Res<Input<KeyCode>>,
+    mouse: Res<Input<MouseButton>>,
+    buttons: Res<Input<GamepadButton>>,
+    mut settings: ResMut<Settings>,
+    mut rebinding: ResMut<Rebinding>
+) {
+    // The click that started the rebind isn't the new binding.
+    if rebinding.is_changed() {
+        return;
+    }
+    let action = match rebinding.0 {
+        Some(action) => action,
+        None => return
+    };
+
+    let binding = keys.get_just_pressed().next().map(|key| Binding::Key(*key))
+        .or_else(|| mouse.get_just_pressed().next().map(|button| Binding::Mouse(*button)))
+        .or_else(|| buttons.get_just_pressed().next().map(|button| Binding::Gamepad(button.button_type)));
+
+    if let Some(binding) = binding {
+        settings.bindings.rebind(action, binding);
+        settings.save();
+        rebinding.0 = None;
+    }
+}
+
+/// Keep the setting labels in step with the settings
+fn update_setting_text(
+    settings: Res<Settings>,
+    rebinding: Res<Rebinding>,
+    mut texts: Query<(&mut Text, &SettingText)>
+) {
+    if !settings.is_changed() && !rebinding.is_changed() {
+        return;
+    }
+
+    for (mut text, field) in texts.iter_mut() {
+        text.sections[0].value = describe_setting(field.0, &settings, rebinding.0);
+    }
+}
@@ -0,0 +1,247 @@
+use std::f32::consts::TAU;
+use bevy::{
+    prelude::*,
+};
+use bevy::asset::{AssetLoader, LoadContext, LoadedAsset};
+use bevy::reflect::TypeUuid;
+use bevy::utils::BoxedFuture;
+use rand::Rng;
+use serde::Deserialize;
+use crate::mob::DEFAULT_ARCHETYPE;
+
+/// Wave table loaded when the game starts, relative to the assets folder.
+pub const DEFAULT_WAVE_TABLE: &str = "waves/default.waves.ron";
+/// Most empty waves passed over at once, so a table without any mobs can't stall the simulation.
+pub const MAX_SKIPPED_WAVES: usize = 100;
+
+/// Every wave of a run, as described by a `.waves.ron` file under `assets/waves/`.
+#[derive(Deserialize, TypeUuid)]
+#[uuid = "5d3c4a8e-9b0f-4f7e-8a61-2c7d1e9b3f40"]
+pub struct WaveTable {
+    /// Waves in the order they are played.
+    pub waves: Vec<WaveDefinition>,
+    /// Generates every wave past the end of `waves`.
+    pub endless: EndlessWaves
+}
+
+impl WaveTable {
+    pub fn wave(
+        &self,
+        wave: usize
+    ) -> WaveDefinition {
+        match self.waves.get(wave) {
+            Some(definition) => definition.clone(),
+            None => self.endless.wave(wave)
+        }
+    }
+
+    /// Index of the first wave from `wave` onwards that spawns any mobs. Empty waves are passed
+    /// over along with their delays, rather than counting as cleared the moment they start.
+    pub fn next_wave(
+        &self,
+        wave: usize
+    ) -> usize {
+        (wave..wave + MAX_SKIPPED_WAVES)
+            .find(|&index| self.wave(index).mob_count() > 0)
+            .unwrap_or(wave)
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct WaveDefinition {
+    /// Seconds to wait after the previous wave is cleared.
+    #[serde(default)]
+    pub delay: f32,
+    pub groups: Vec<SpawnGroup>
+}
+
+impl WaveDefinition {
+    pub fn mob_count(&self) -> usize {
+        self.groups.iter().map(|group| group.count).sum()
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct SpawnGroup {
+    pub count: usize,
+    /// Archetype ID from the `MobArchetypes` registry.
+    #[serde(default = "default_mob")]
+    pub mob: String,
+    /// Multiplier on the archetype's health and contact damage.
+    #[serde(default = "default_strength")]
+    pub strength: f32,
+    #[serde(default)]
+    pub pattern: SpawnPattern
+}
+
+#[derive(Deserialize, Clone)]
+pub struct EndlessWaves {
+    #[serde(default)]
+    pub delay: f32,
+    /// Wave `n` spawns `scale * n^power` mobs.
+    pub scale: f32,
+    pub power: u32,
+    #[serde(default = "default_mob")]
+    pub mob: String,
+    #[serde(default = "default_strength")]
+    pub strength: f32,
+    #[serde(default)]
+    pub pattern: SpawnPattern
+}
+
+impl EndlessWaves {
+    pub fn wave(
+        &self,
+        wave: usize
+    ) -> WaveDefinition {
+        WaveDefinition {
+            delay: self.delay,
+            groups: vec![SpawnGroup {
+                count: (self.scale * (wave as f32).powi(self.power as i32)) as usize,
+                mob: self.mob.clone(),
+                strength: self.strength,
+                pattern: self.pattern.clone()
+            }]
+        }
+    }
+}
+
+/// Where the mobs of a group appear on the surface.
+#[derive(Deserialize, Clone)]
+pub enum SpawnPattern {
+    /// Uniformly inside a square with its lowest corner at `origin`.
+    Square { origin: (f32, f32), size: f32 },
+    /// Evenly spaced on a circle around the player.
+    Ring { radius: f32 },
+    /// Uniformly inside a circle around a fixed point.
+    Cluster { centre: (f32, f32), radius: f32 },
+}
+
+impl Default for SpawnPattern {
+    fn default() -> Self {
+        SpawnPattern::Square { origin: (0.0, 0.0), size: 20.0 }
+    }
+}
+
+impl SpawnPattern {
+    /// Position on the ground of mob `index` out of `count`.
+    pub fn position(
+        &self,
+        prng: &mut impl Rng,
+        index: usize,
+        count: usize,
+        player_position: Vec3
+    ) -> Vec3 {
+        match self {
+            SpawnPattern::Square { origin, size } => {
+                Vec3::new(origin.0 + prng.gen::<f32>() * size,
+                          0.5,
+                          origin.1 + prng.gen::<f32>() * size)
+            }
+            SpawnPattern::Ring { radius } => {
+                let angle = TAU * index as f32 / count.max(1) as f32;
+                Vec3::new(player_position.x + radius * angle.cos(),
+                          0.5,
+                          player_position.z + radius * angle.sin())
+            }
+            SpawnPattern::Cluster { centre, radius } => {
+                let angle = TAU * prng.gen::<f32>();
+                let distance = radius * prng.gen::<f32>().sqrt();
+                Vec3::new(centre.0 + distance * angle.cos(),
+                          0.5,
+                          centre.1 + distance * angle.sin())
+            }
+        }
+    }
+}
+
+fn default_mob() -> String {
+    DEFAULT_ARCHETYPE.to_string()
+}
+
+fn default_strength() -> f32 {
+    1.0
+}
+
+#[derive(Default)]
+pub struct WaveTableLoader;
+
+impl AssetLoader for WaveTableLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let table: WaveTable = ron::de::from_bytes(bytes)?;
+            load_context.set_default_asset(LoadedAsset::new(table));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["waves.ron"]
+    }
+}
+
+/// Handle to the wave table used by the simulation, kept alive so the asset stays loaded.
+#[derive(Resource)]
+pub struct ActiveWaveTable(pub Handle<WaveTable>);
+
+pub fn load_wave_table(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>
+) {
+    commands.insert_resource(ActiveWaveTable(asset_server.load(DEFAULT_WAVE_TABLE)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn group(count: usize) -> SpawnGroup {
+        SpawnGroup { count, mob: default_mob(), strength: default_strength(), pattern: SpawnPattern::default() }
+    }
+
+    fn table(counts: &[usize]) -> WaveTable {
+        WaveTable {
+            waves: counts.iter()
+                .map(|&count| WaveDefinition { delay: 2.0, groups: vec![group(count)] })
+                .collect(),
+            endless: EndlessWaves {
+                delay: 2.0,
+                scale: 1.0,
+                power: 2,
+                mob: default_mob(),
+                strength: default_strength(),
+                pattern: SpawnPattern::default()
+            }
+        }
+    }
+
+    #[test]
+    fn empty_waves_are_passed_over() {
+        let table = table(&[2, 0, 3]);
+
+        assert_eq!(table.next_wave(0), 0);
+        assert_eq!(table.next_wave(1), 2);
+        assert_eq!(table.next_wave(2), 2);
+    }
+
+    #[test]
+    fn waves_without_groups_are_empty() {
+        let mut table = table(&[2]);
+        table.waves.push(WaveDefinition { delay: 0.0, groups: Vec::new() });
+
+        assert_eq!(table.next_wave(1), 2);
+        assert_eq!(table.wave(2).mob_count(), 4);
+    }
+
+    #[test]
+    fn a_table_without_mobs_stays_put() {
+        let mut table = table(&[]);
+        table.endless.scale = 0.0;
+
+        assert_eq!(table.next_wave(5), 5);
+    }
+}
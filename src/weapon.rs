@@ -0,0 +1,456 @@
+use std::f32::consts::TAU;
+use std::time::Duration;
+use bevy::{
+    prelude::*,
+};
+use iyes_loopless::prelude::*;
+use serde::{Deserialize, Serialize};
+use crate::health::{DamageEvent, DamageKind, Health};
+use crate::mob::Mob;
+use crate::player::{Player, PLAYER_COLOUR, Projectile};
+
+/// Radius of a single orbiting blade.
+pub const BLADE_RADIUS: f32 = 0.4;
+/// Half width of a beam, mobs closer than this to its line are hit.
+pub const BEAM_WIDTH: f32 = 0.4;
+/// Seconds pulse and beam effects stay on screen.
+pub const EFFECT_LIFETIME: f32 = 0.15;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum WeaponKind {
+    /// Projectiles that curve in towards the mobs.
+    Seeker,
+    /// A fan of fast projectiles fired straight at the nearest mob.
+    SpreadShot,
+    /// Blades circling the player, cutting every mob they pass through.
+    OrbitingBlades,
+    /// Damages every mob within range at once.
+    AreaPulse,
+    /// Instant line towards the nearest mob, hitting the first mobs along it.
+    Beam,
+}
+
+/// A weapon held by the player. Each one is a child entity of the player, so any number can be
+/// held at once and they all fire on their own cooldowns.
+#[derive(Component, Clone, Debug)]
+pub struct Weapon {
+    pub kind: WeaponKind,
+    /// Shots per second. Blades hit each mob they touch this often.
+    pub fire_rate: f32,
+    pub damage: f32,
+    /// Projectiles per shot, or number of blades.
+    pub projectile_count: usize,
+    /// Angle in radians the projectiles of a shot are fanned across.
+    pub spread: f32,
+    /// Launch speed of projectiles, or angular speed of blades in radians per second.
+    pub speed: f32,
+    /// Seconds before projectiles expire.
+    pub lifetime: f32,
+    /// Acceleration of projectiles towards the mobs, 0 flies straight.
+    pub homing: f32,
+    /// Extra mobs a projectile or beam goes through before it stops.
+    pub pierce: usize,
+    /// Which mob projectiles home in on and shots are aimed at.
+    pub targeting: Targeting,
+    /// Orbit radius of blades, radius of pulses and length of beams.
+    pub range: f32,
+    cooldown: Timer,
+    /// Current angle of the blades around the player.
+    phase: f32
+}
+
+impl Weapon {
+    pub fn new(kind: WeaponKind) -> Self {
+        let weapon = Weapon {
+            kind,
+            fire_rate: 1.0,
+            damage: 3.0,
+            projectile_count: 1,
+            spread: 0.0,
+            speed: 0.0,
+            lifetime: 0.0,
+            homing: 0.0,
+            pierce: 0,
+            targeting: Targeting::Nearest,
+            range: 0.0,
+            cooldown: Timer::default(),
+            phase: 0.0
+        };
+
+        let weapon = match kind {
+            WeaponKind::Seeker => Weapon {
+                fire_rate: 1.0 / 0.3,
+                lifetime: 10.0,
+                homing: 5.0,
+                targeting: Targeting::Locked,
+                ..weapon
+            },
+            WeaponKind::SpreadShot => Weapon {
+                fire_rate: 1.2,
+                damage: 2.0,
+                projectile_count: 5,
+                spread: 0.8,
+                speed: 14.0,
+                lifetime: 1.2,
+                pierce: 1,
+                ..weapon
+            },
+            WeaponKind::OrbitingBlades => Weapon {
+                fire_rate: 4.0,
+                damage: 2.0,
+                projectile_count: 3,
+                speed: 3.0,
+                range: 2.5,
+                targeting: Targeting::None,
+                ..weapon
+            },
+            WeaponKind::AreaPulse => Weapon {
+                fire_rate: 0.5,
+                damage: 6.0,
+                range: 5.0,
+                targeting: Targeting::None,
+                ..weapon
+            },
+            WeaponKind::Beam => Weapon {
+                fire_rate: 0.8,
+                damage: 8.0,
+                pierce: 3,
+                range: 15.0,
+                ..weapon
+            },
+        };
+
+        weapon.with_fire_rate(weapon.fire_rate)
+    }
+
+    pub fn with_fire_rate(
+        mut self,
+        fire_rate: f32
+    ) -> Self {
+        self.set_fire_rate(fire_rate);
+        self
+    }
+
+    pub fn set_fire_rate(
+        &mut self,
+        fire_rate: f32
+    ) {
+        self.fire_rate = fire_rate;
+        self.cooldown = Timer::new(Duration::from_secs_f32(1.0 / fire_rate), TimerMode::Repeating);
+    }
+
+    /// Advance the cooldown, returning whether the weapon fires this tick.
+    fn tick(
+        &mut self,
+        delta: Duration
+    ) -> bool {
+        self.cooldown.tick(delta);
+        self.cooldown.just_finished()
+    }
+}
+
+/// One of the blades of an `OrbitingBlades` weapon.
+#[derive(Component)]
+pub struct Blade {
+    pub weapon: Entity,
+    pub index: usize,
+    pub pos: Vec3
+}
+
+/// Short lived visual for shots that hit instantly, scaled from `from` to `to` over its lifetime.
+#[derive(Component)]
+pub struct WeaponEffect {
+    pub lifetime: Timer,
+    pub from: Vec3,
+    pub to: Vec3
+}
+
+/// How a weapon picks the mob its shots go after.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Targeting {
+    Nearest,
+    LowestHealth,
+    HighestStrength,
+    /// Locks on to the nearest mob and keeps after it, picking a new one only once it dies.
+    Locked,
+    /// No homing at all.
+    None,
+}
+
+impl Targeting {
+    /// Ranking of a mob as a target from `position`, lower is better. Ties go to the nearer mob.
+    fn rank(
+        self,
+        position: Vec3,
+        mob: &Mob,
+        health: &Health
+    ) -> Option<(f32, f32)> {
+        let mut offset = mob.pos - position;
+        offset.y = 0.;
+        let distance = offset.length_squared();
+
+        match self {
+            Targeting::Nearest | Targeting::Locked => Some((distance, distance)),
+            Targeting::LowestHealth => Some((health.current, distance)),
+            Targeting::HighestStrength => Some((-mob.strength, distance)),
+            Targeting::None => None,
+        }
+    }
+
+    /// Best living target among `mobs`, or `None` when there is nothing to go after.
+    pub fn select<'a>(
+        self,
+        position: Vec3,
+        mobs: impl Iterator<Item = (Entity, &'a Mob, &'a Health)>
+    ) -> Option<Entity> {
+        mobs.filter(|(_, _, health)| !health.is_dead())
+            .filter_map(|(entity, mob, health)| self.rank(position, mob, health).map(|rank| (rank, entity)))
+            .min_by(|(a, entity_a), (b, entity_b)| {
+                a.0.total_cmp(&b.0).then(a.1.total_cmp(&b.1)).then(entity_a.cmp(entity_b))
+            })
+            .map(|(_, entity)| entity)
+    }
+}
+
+/// The mob the player is attack-moving on, if it is still alive.
+fn attack_focus(
+    player: &Player,
+    mobs: &Query<(Entity, &Mob, &Health)>
+) -> Option<Entity> {
+    player.attack_target.filter(|target| mobs.get(*target).map_or(false, |(_, _, health)| !health.is_dead()))
+}
+
+/// Horizontal direction from `position` to the target a weapon fires at, the `focus` if there is
+/// one. Shots are aimed once, so weapons that lock on or don't home are aimed at the nearest mob.
+fn aim(
+    targeting: Targeting,
+    position: Vec3,
+    focus: Option<Entity>,
+    mobs: &Query<(Entity, &Mob, &Health)>
+) -> Option<Vec3> {
+    let targeting = match targeting {
+        Targeting::Locked | Targeting::None => Targeting::Nearest,
+        targeting => targeting
+    };
+
+    let target = focus.or_else(|| targeting.select(position, mobs.iter()))?;
+    let (_, mob, _) = mobs.get(target).ok()?;
+
+    let mut offset = mob.pos - position;
+    offset.y = 0.;
+    offset.try_normalize()
+}
+
+pub fn fire_weapons(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    step: Res<FixedTimestepInfo>,
+    mut damage: EventWriter<DamageEvent>,
+    players: Query<&Player>,
+    mut weapons: Query<(Entity, &Parent, &mut Weapon)>,
+    mut blades: Query<(Entity, &mut Transform, &mut Blade), Without<Mob>>,
+    mobs: Query<(Entity, &Mob, &Health)>
+) {
+    let dt = step.timestep().as_secs_f32();
+
+    for (weapon_entity, parent, mut weapon) in weapons.iter_mut() {
+        let player = match players.get(parent.get()) {
+            Ok(player) => player,
+            Err(_) => continue
+        };
+
+        let fired = weapon.tick(step.timestep());
+        let focus = attack_focus(player, &mobs);
+        let (source, amount, kind) = (parent.get(), weapon.damage, DamageKind::Weapon(weapon.kind));
+        let hit = move |target: Entity| DamageEvent { source: Some(source), target, amount, kind };
+
+        match weapon.kind {
+            WeaponKind::Seeker | WeaponKind::SpreadShot => {
+                if !fired {
+                    continue;
+                }
+
+                let aim = aim(weapon.targeting, player.pos, focus, &mobs).unwrap_or(Vec3::X);
+
+                // Homing shots stay on an attack-move target until it dies.
+                let targeting = match (focus, weapon.targeting) {
+                    (Some(_), Targeting::None) | (None, _) => weapon.targeting,
+                    (Some(_), _) => Targeting::Locked
+                };
+
+                for i in 0..weapon.projectile_count {
+                    let angle = if weapon.projectile_count > 1 {
+                        weapon.spread * (i as f32 / (weapon.projectile_count - 1) as f32 - 0.5)
+                    } else {
+                        0.0
+                    };
+                    let direction = Quat::from_rotation_y(angle) * aim;
+
+                    commands.spawn(PbrBundle {
+                        mesh: meshes.add(Mesh::from(shape::Cube { size: 0.3 })),
+                        material: materials.add(PLAYER_COLOUR.into()),
+                        transform: Transform::from_translation(player.pos),
+                        ..default()
+                    }).insert(Projectile {
+                        source: parent.get(),
+                        weapon: weapon.kind,
+                        pos: player.pos,
+                        vel: direction * weapon.speed,
+                        acc: Vec3::default(),
+                        damage: weapon.damage,
+                        homing: weapon.homing,
+                        targeting,
+                        target: focus,
+                        pierce: weapon.pierce,
+                        hits: Vec::new(),
+                        lifetime: Timer::new(Duration::from_secs_f32(weapon.lifetime), TimerMode::Once),
+                    });
+                }
+            }
+            WeaponKind::OrbitingBlades => {
+                weapon.phase = (weapon.phase + weapon.speed * dt).rem_euclid(TAU);
+
+                let mut count = 0;
+                for (blade_entity, mut transform, mut blade) in blades.iter_mut() {
+                    if blade.weapon != weapon_entity {
+                        continue;
+                    }
+
+                    // Blades beyond the count, after it has been lowered, are removed.
+                    if blade.index >= weapon.projectile_count {
+                        commands.entity(blade_entity).despawn();
+                        continue;
+                    }
+                    count += 1;
+
+                    let angle = weapon.phase + TAU * blade.index as f32 / weapon.projectile_count as f32;
+                    blade.pos = player.pos + Quat::from_rotation_y(angle) * Vec3::X * weapon.range;
+                    transform.translation = blade.pos;
+
+                    if fired {
+                        for (entity, mob, _) in mobs.iter() {
+                            let mut offset = mob.pos - blade.pos;
+                            offset.y = 0.;
+
+                            if offset.length() < BLADE_RADIUS + mob.size * 0.5 {
+                                damage.send(hit(entity));
+                            }
+                        }
+                    }
+                }
+
+                // When blades are missing the whole set is replaced, so they stay evenly spaced.
+                // New blades are placed on the next tick.
+                if count < weapon.projectile_count {
+                    for (blade_entity, _, blade) in blades.iter() {
+                        if blade.weapon == weapon_entity && blade.index < weapon.projectile_count {
+                            commands.entity(blade_entity).despawn();
+                        }
+                    }
+
+                    for index in 0..weapon.projectile_count {
+                        commands.spawn(PbrBundle {
+                            mesh: meshes.add(Mesh::from(shape::Icosphere { radius: BLADE_RADIUS, subdivisions: 1 })),
+                            material: materials.add(PLAYER_COLOUR.into()),
+                            transform: Transform::from_translation(player.pos),
+                            ..default()
+                        }).insert(Blade {
+                            weapon: weapon_entity,
+                            index,
+                            pos: player.pos
+                        });
+                    }
+                }
+            }
+            WeaponKind::AreaPulse => {
+                if !fired {
+                    continue;
+                }
+
+                for (entity, mob, _) in mobs.iter() {
+                    let mut offset = mob.pos - player.pos;
+                    offset.y = 0.;
+
+                    if offset.length() < weapon.range + mob.size * 0.5 {
+                        damage.send(hit(entity));
+                    }
+                }
+
+                commands.spawn(PbrBundle {
+                    mesh: meshes.add(Mesh::from(shape::Torus { radius: 1.0, ring_radius: 0.05, ..default() })),
+                    material: materials.add(PLAYER_COLOUR.into()),
+                    transform: Transform::from_translation(player.pos).with_scale(Vec3::splat(0.1)),
+                    ..default()
+                }).insert(WeaponEffect {
+                    lifetime: Timer::new(Duration::from_secs_f32(EFFECT_LIFETIME), TimerMode::Once),
+                    from: Vec3::splat(0.1),
+                    to: Vec3::new(weapon.range, 1.0, weapon.range)
+                });
+            }
+            WeaponKind::Beam => {
+                if !fired {
+                    continue;
+                }
+
+                let direction = match aim(weapon.targeting, player.pos, focus, &mobs) {
+                    Some(direction) => direction,
+                    None => continue
+                };
+
+                // Everything close enough to the line, nearest first.
+                let mut hits: Vec<(f32, Entity)> = mobs.iter()
+                    .filter_map(|(entity, mob, _)| {
+                        let mut offset = mob.pos - player.pos;
+                        offset.y = 0.;
+                        let along = offset.dot(direction);
+                        let across = (offset - direction * along).length();
+
+                        if along > 0. && along < weapon.range && across < BEAM_WIDTH + mob.size * 0.5 {
+                            Some((along, entity))
+                        } else {
+                            None
+                        }
+                    })
+                    .collect();
+                hits.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+
+                for (_, entity) in hits.iter().take(weapon.pierce + 1) {
+                    damage.send(hit(*entity));
+                }
+
+                // A beam that used up its pierce ends on the last mob it hit.
+                let length = hits.get(weapon.pierce).map_or(weapon.range, |(along, _)| *along);
+
+                let scale = Vec3::new(length, 1.0, 1.0);
+                commands.spawn(PbrBundle {
+                    mesh: meshes.add(Mesh::from(shape::Box::new(1.0, 0.15, 0.15))),
+                    material: materials.add(PLAYER_COLOUR.into()),
+                    transform: Transform::from_translation(player.pos + direction * length * 0.5)
+                        .with_rotation(Quat::from_rotation_arc(Vec3::X, direction))
+                        .with_scale(scale),
+                    ..default()
+                }).insert(WeaponEffect {
+                    lifetime: Timer::new(Duration::from_secs_f32(EFFECT_LIFETIME), TimerMode::Once),
+                    from: scale,
+                    to: scale
+                });
+            }
+        }
+    }
+}
+
+pub fn update_weapon_effects(
+    mut commands: Commands,
+    step: Res<FixedTimestepInfo>,
+    mut effects: Query<(Entity, &mut Transform, &mut WeaponEffect)>
+) {
+    for (entity, mut transform, mut effect) in effects.iter_mut() {
+        effect.lifetime.tick(step.timestep());
+        transform.scale = effect.from.lerp(effect.to, effect.lifetime.percent());
+
+        if effect.lifetime.finished() {
+            commands.entity(entity).despawn();
+        }
+    }
+}